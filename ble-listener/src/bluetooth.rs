@@ -1,114 +1,282 @@
-use btleplug::api::{Central, Peripheral as _, ScanFilter};
-use btleplug::platform::{Adapter, Peripheral};
+use btleplug::api::{CharPropFlags, Service, ValueNotification};
 use futures::stream::StreamExt;
-use reqwest::Client;
+use std::collections::{BTreeSet, VecDeque};
 use std::error::Error;
+use std::fmt;
 use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::time;
 use uuid::Uuid;
 
-use crate::config::{BATTERY_LEVEL_UUID, BATTERY_SERVICE_UUID, DEVICE_NAME};
-use crate::event::send_button_event;
-
-pub async fn find_device(adapter: &Adapter) -> Result<Peripheral, Box<dyn Error>> {
-    println!("🔍 Scanning for {} device...", DEVICE_NAME);
-
-    adapter
-        .start_scan(ScanFilter::default())
-        .await
-        .map_err(|e| format!("Failed to start BLE scan: {}", e))?;
-
-    time::sleep(Duration::from_secs(10)).await;
+use crate::buttons::ButtonTracker;
+use crate::commands::{confirm, deliver_pending, CommandServer};
+use crate::config::{
+    BATTERY_LEVEL_UUID, BATTERY_SERVICE_UUID, BUTTON_SERVICE_UUID, COMMAND_CHAR_UUID,
+    COMMAND_POLL_INTERVAL_MS, DEVICE_NAME, HEARTBEAT_INTERVAL_MS,
+};
+use crate::decoders::{decode_battery, decoder_for, Reading};
+use crate::event::{connection_event, now_micros, now_millis, ButtonEvent, Trace, BATTERY_BUTTON};
+use crate::transport::{Advertisement, Device, Disconnections, Scanner};
+
+/// Which devices to connect to. A device is picked up if it matches any of the criteria
+/// that are set.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFilter {
+    pub name_prefix: Option<String>,
+    pub addresses: Vec<String>,
+    pub service: Option<Uuid>,
+}
 
-    let peripherals = adapter
-        .peripherals()
-        .await
-        .map_err(|e| format!("Failed to get peripherals: {}", e))?;
+impl DeviceFilter {
+    /// Builds a filter from the given criteria; without any, devices are matched by the
+    /// `DEVICE_NAME` prefix.
+    pub fn new(name_prefix: Option<String>, addresses: Vec<String>, service: Option<Uuid>) -> Self {
+        let addresses: Vec<String> = addresses
+            .iter()
+            .map(|address| address.trim().to_string())
+            .filter(|address| !address.is_empty())
+            .collect();
+        let name_prefix = match name_prefix {
+            None if addresses.is_empty() && service.is_none() => Some(DEVICE_NAME.to_string()),
+            name_prefix => name_prefix,
+        };
+        Self {
+            name_prefix,
+            addresses,
+            service,
+        }
+    }
 
-    if peripherals.is_empty() {
-        return Err("No BLE devices found".into());
+    /// Services to filter scans on: the configured one, or the button service.
+    pub fn scan_services(&self) -> Vec<Uuid> {
+        match self.service {
+            Some(service) => vec![service],
+            None => Uuid::parse_str(BUTTON_SERVICE_UUID).into_iter().collect(),
+        }
     }
 
-    println!("Found {} BLE devices:", peripherals.len());
+    pub fn matches(&self, address: &str, advertisement: &Advertisement) -> bool {
+        let name = advertisement.name.as_deref().unwrap_or_default();
+        self.name_prefix
+            .as_ref()
+            .is_some_and(|prefix| name.starts_with(prefix.as_str()))
+            || self
+                .addresses
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(address))
+            || self
+                .service
+                .is_some_and(|service| advertisement.services.contains(&service))
+    }
+}
 
-    for peripheral in peripherals {
-        let properties = peripheral
-            .properties()
-            .await
-            .map_err(|e| format!("Failed to get device properties: {}", e))?;
+impl fmt::Display for DeviceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(prefix) = &self.name_prefix {
+            criteria.push(format!("name {}*", prefix));
+        }
+        if !self.addresses.is_empty() {
+            criteria.push(format!("address {}", self.addresses.join("/")));
+        }
+        if let Some(service) = &self.service {
+            criteria.push(format!("service {}", service));
+        }
+        write!(f, "{}", criteria.join(" or "))
+    }
+}
 
-        let name = properties
-            .and_then(|p| p.local_name)
-            .unwrap_or_else(|| "Unknown".to_string());
+/// Scans for `timeout`, handing every device matching `filter` to `found` as soon as it is
+/// discovered. A device may be handed over more than once.
+pub async fn discover_devices<S: Scanner>(
+    scanner: &S,
+    filter: &DeviceFilter,
+    timeout: Duration,
+    mut found: impl FnMut(S::Device),
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    eprintln!("🔍 Scanning for devices matching {}...", filter);
+
+    scanner
+        .start_scan(filter.scan_services())
+        .await
+        .map_err(|e| format!("Failed to start BLE scan: {}", e))?;
 
-        println!("  - {} ({})", name, peripheral.address());
+    let mut discoveries = match scanner.discoveries().await {
+        Ok(discoveries) => discoveries,
+        Err(e) => {
+            let _ = scanner.stop_scan().await;
+            return Err(format!("Failed to watch for discovered devices: {}", e).into());
+        }
+    };
+    let deadline = time::sleep(timeout);
+    tokio::pin!(deadline);
 
-        if name == DEVICE_NAME {
-            println!("✅ Found device: {} ({})", name, peripheral.address());
-            return Ok(peripheral);
+    loop {
+        tokio::select! {
+            Some(peripheral) = discoveries.next() => {
+                let address = peripheral.address();
+                let advertisement = match peripheral.advertisement().await {
+                    Ok(advertisement) => advertisement,
+                    Err(e) => {
+                        eprintln!("❌ Failed to get properties of {}: {}", address, e);
+                        continue;
+                    }
+                };
+                let name = advertisement.name.as_deref().unwrap_or("Unknown");
+
+                if filter.matches(&address, &advertisement) {
+                    eprintln!("✅ Found device: {} ({})", name, address);
+                    found(peripheral);
+                } else {
+                    eprintln!("  - {} ({})", name, address);
+                }
+            }
+            _ = &mut deadline => break,
         }
     }
 
-    Err(format!(
-        "{} device not found. Make sure your micro:bit is running and advertising.",
-        DEVICE_NAME
-    )
-    .into())
+    if let Err(e) = scanner.stop_scan().await {
+        eprintln!("❌ Failed to stop BLE scan: {}", e);
+    }
+    Ok(())
 }
 
-pub fn handle_button_notification(data: &[u8], client: &Client) {
-    if data.is_empty() {
-        println!("Received empty notification data");
+/// Decodes a notification with the decoder of its characteristic and forwards the reading.
+/// Notifications from characteristics without a decoder are only logged.
+pub fn handle_notification(
+    notification: &ValueNotification,
+    buttons: &mut ButtonTracker,
+    events: &UnboundedSender<ButtonEvent>,
+    device: &str,
+) {
+    let received_at = now_micros();
+    let Some(decode) = decoder_for(notification.uuid) else {
+        eprintln!(
+            "Ignoring notification from unknown characteristic {}",
+            notification.uuid
+        );
         return;
+    };
+    if let Some(reading) = decode(&notification.value) {
+        forward_reading(reading, received_at, buttons, events, device);
     }
+}
 
-    let value = data[0];
-    let rt = tokio::runtime::Handle::current();
-
-    match value {
-        1 => {
-            println!("🔴 Button A (LEFT) PRESSED");
-            let client = client.clone();
-            rt.spawn(async move {
-                send_button_event(&client, "A", "PRESSED").await;
-            });
+/// Forwards a reading as events. Button releases are attributed to the buttons `buttons`
+/// saw pressed, one event per button with the press duration; a release without a
+/// matching press is forwarded as `ANY`.
+fn forward_reading(
+    reading: Reading,
+    received_at: u64,
+    buttons: &mut ButtonTracker,
+    events: &UnboundedSender<ButtonEvent>,
+    device: &str,
+) {
+    let button_event = |button: &str, state: &str, device_tick, duration_ms| ButtonEvent {
+        button: button.to_string(),
+        state: state.to_string(),
+        timestamp: now_millis(),
+        device: Some(device.to_string()),
+        battery: None,
+        duration_ms,
+        trace: Some(Trace {
+            device_tick,
+            listener_received_at: Some(received_at),
+            ..Default::default()
+        }),
+    };
+    let forwarded = match reading {
+        Reading::Button {
+            button,
+            state: "RELEASED",
+            device_tick,
+        } => {
+            let releases = buttons.release(device_tick, received_at);
+            if releases.is_empty() {
+                vec![button_event(button, "RELEASED", device_tick, None)]
+            } else {
+                releases
+                    .into_iter()
+                    .map(|release| {
+                        eprintln!(
+                            "⚪ Button {} released after {} ms",
+                            release.button, release.duration_ms
+                        );
+                        button_event(
+                            release.button,
+                            "RELEASED",
+                            device_tick,
+                            Some(release.duration_ms),
+                        )
+                    })
+                    .collect()
+            }
         }
-        2 => {
-            println!("🔵 Button B (RIGHT) PRESSED");
-            let client = client.clone();
-            rt.spawn(async move {
-                send_button_event(&client, "B", "PRESSED").await;
-            });
+        Reading::Button {
+            button,
+            state,
+            device_tick,
+        } => {
+            buttons.press(button, device_tick, received_at);
+            vec![button_event(button, state, device_tick, None)]
         }
-        0 => {
-            println!("⚪ Button RELEASED");
-            let client = client.clone();
-            rt.spawn(async move {
-                send_button_event(&client, "ANY", "RELEASED").await;
-            });
+        Reading::Battery(level) => vec![ButtonEvent {
+            button: BATTERY_BUTTON.to_string(),
+            state: "REPORTED".to_string(),
+            timestamp: now_millis(),
+            device: Some(device.to_string()),
+            battery: Some(level),
+            duration_ms: None,
+            trace: None,
+        }],
+    };
+    for event in forwarded {
+        if events.send(event.clone()).is_err() {
+            eprintln!(
+                "❌ Event forwarder has stopped; dropping {} {}",
+                event.button, event.state
+            );
         }
-        _ => println!("Unknown button value: {}", value),
     }
 }
 
-pub async fn connect_and_listen(
-    peripheral: &Peripheral,
-    client: &Client,
-) -> Result<(), Box<dyn Error>> {
-    println!("🔗 Connecting to device...");
+/// Why [`connect_and_listen`] stopped listening.
+#[derive(Debug, PartialEq)]
+pub enum Stopped {
+    Disconnected,
+    Interrupted,
+}
+
+/// Subscribes to the device's notifications and forwards button events to `events`,
+/// starting with a `CONNECTION` / `CONNECTED` event, until the device shows up in
+/// `disconnections`, its notification stream closes or Ctrl+C is pressed. Commands for
+/// the device are polled from `server`, if there is one. Every
+/// [`HEARTBEAT_INTERVAL_MS`] the battery level is read again, or `CONNECTED` repeated
+/// if the device has none, so an idle device is not taken for offline. Errors are only returned before
+/// the `CONNECTED` event is sent, so the caller reports `DISCONNECTED` exactly when it
+/// gets `Ok`.
+pub async fn connect_and_listen<D: Device>(
+    peripheral: &D,
+    server: Option<&CommandServer>,
+    events: &UnboundedSender<ButtonEvent>,
+    mut disconnections: Disconnections,
+) -> Result<Stopped, Box<dyn Error + Send + Sync>> {
+    let device = peripheral.address();
+    eprintln!("🔗 Connecting to {}...", device);
 
     peripheral.connect().await?;
-    println!("🔗 Connected: {}", peripheral.is_connected().await?);
+    eprintln!("🔗 Connected: {}", peripheral.is_connected().await?);
 
-    peripheral.discover_services().await?;
-    let services = peripheral.services();
+    let services = peripheral.discover_services().await?;
 
-    println!("\n📋 Available services ({}):", services.len());
+    eprintln!("\n📋 Available services ({}):", services.len());
 
-    let mut button_char_found = false;
+    let mut subscribed = false;
+    let command_char_uuid = Uuid::parse_str(COMMAND_CHAR_UUID)?;
+    let mut command_char = None;
 
     for service in &services {
-        println!("  🔹 Service {}", service.uuid);
+        eprintln!("  🔹 Service {}", service.uuid);
 
         for characteristic in &service.characteristics {
             let props: Vec<String> = characteristic
@@ -116,100 +284,315 @@ pub async fn connect_and_listen(
                 .iter()
                 .map(|p| format!("{:?}", p))
                 .collect();
-            println!(
+            eprintln!(
                 "    └─ Characteristic {} ({})",
                 characteristic.uuid,
                 props.join(", ")
             );
 
-            if characteristic
-                .properties
-                .contains(btleplug::api::CharPropFlags::NOTIFY)
-            {
-                println!(
-                    "    📡 Attempting to subscribe to notifications on {}",
+            if characteristic.uuid == command_char_uuid {
+                // Commands need the notification the device confirms them with.
+                if characteristic.properties.contains(CharPropFlags::NOTIFY) {
+                    match peripheral.subscribe(characteristic).await {
+                        Ok(_) => command_char = Some(characteristic.clone()),
+                        Err(e) => {
+                            eprintln!("    ❌ Failed to subscribe to command confirmations: {}", e)
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if !characteristic.properties.contains(CharPropFlags::NOTIFY) {
+                continue;
+            }
+            if decoder_for(characteristic.uuid).is_none() {
+                eprintln!(
+                    "    ⏭️  Not subscribing to unknown characteristic {}",
                     characteristic.uuid
                 );
+                continue;
+            }
+            eprintln!(
+                "    📡 Attempting to subscribe to notifications on {}",
+                characteristic.uuid
+            );
 
-                match peripheral.subscribe(&characteristic).await {
-                    Ok(_) => {
-                        println!("    ✅ Successfully subscribed to {}", characteristic.uuid);
-                        button_char_found = true;
-                    }
-                    Err(e) => {
-                        println!(
-                            "    ❌ Failed to subscribe to {}: {}",
-                            characteristic.uuid, e
-                        );
-                    }
+            match peripheral.subscribe(characteristic).await {
+                Ok(_) => {
+                    eprintln!("    ✅ Successfully subscribed to {}", characteristic.uuid);
+                    subscribed = true;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "    ❌ Failed to subscribe to {}: {}",
+                        characteristic.uuid, e
+                    );
                 }
             }
         }
     }
 
-    if !button_char_found {
-        return Err("❌ No notify characteristics found!".into());
+    if !subscribed {
+        return Err("❌ No known notify characteristics found!".into());
     }
 
-    read_battery_level(peripheral, &services).await;
+    let mut notification_stream = peripheral.notifications().await?;
+    let mut buttons = ButtonTracker::default();
+    let _ = events.send(connection_event(&device, "CONNECTED"));
+    read_battery_level(peripheral, &services, &mut buttons, events, &device).await;
 
-    println!("\n🎮 Ready! Press buttons A or B on your micro:bit...");
-    println!("📡 Events will be sent to the web browser at http://127.0.0.1:3000");
-    println!("Press Ctrl+C to stop\n");
+    eprintln!("\n🎮 Ready! Press buttons A or B on {}...", device);
+    eprintln!("Press Ctrl+C to stop\n");
 
-    let mut notification_stream = peripheral.notifications().await?;
+    let mut command_poll = time::interval(Duration::from_millis(COMMAND_POLL_INTERVAL_MS));
+    let mut awaiting = VecDeque::new();
+    let heartbeat_interval = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+    let mut heartbeat = time::interval_at(
+        time::Instant::now() + heartbeat_interval,
+        heartbeat_interval,
+    );
 
     loop {
         tokio::select! {
-            Some(data) = notification_stream.next() => {
-                handle_button_notification(&data.value, client);
+            notification = notification_stream.next() => {
+                let Some(data) = notification else {
+                    eprintln!("\n📴 {} disconnected", device);
+                    return Ok(Stopped::Disconnected);
+                };
+                if data.uuid != command_char_uuid {
+                    handle_notification(&data, &mut buttons, events, &device);
+                } else if let Some(server) = server {
+                    confirm(server, &mut awaiting, &data.value).await;
+                }
+            }
+            Some(address) = disconnections.next() => {
+                if address == device {
+                    eprintln!("\n📴 {} disconnected", device);
+                    return Ok(Stopped::Disconnected);
+                }
+            }
+            _ = command_poll.tick(), if server.is_some() => {
+                if let Some(server) = server {
+                    deliver_pending(
+                        peripheral,
+                        server,
+                        &device,
+                        command_char.as_ref(),
+                        &mut awaiting,
+                    )
+                    .await;
+                }
+            }
+            _ = heartbeat.tick() => {
+                if !read_battery_level(peripheral, &services, &mut buttons, events, &device).await {
+                    let _ = events.send(connection_event(&device, "CONNECTED"));
+                }
             }
             _ = tokio::signal::ctrl_c() => {
-                println!("\n🛑 Stopping...");
-                break;
+                eprintln!("\n🛑 Stopping...");
+                return Ok(Stopped::Interrupted);
             }
         }
     }
-
-    Ok(())
 }
 
-async fn read_battery_level(
-    peripheral: &Peripheral,
-    services: &std::collections::BTreeSet<btleplug::api::Service>,
-) {
+/// Forwards the device's battery level, returning whether there was one to forward.
+async fn read_battery_level<D: Device>(
+    peripheral: &D,
+    services: &BTreeSet<Service>,
+    buttons: &mut ButtonTracker,
+    events: &UnboundedSender<ButtonEvent>,
+    device: &str,
+) -> bool {
     let battery_service_uuid = match Uuid::parse_str(BATTERY_SERVICE_UUID) {
         Ok(uuid) => uuid,
-        Err(_) => return,
+        Err(_) => return false,
     };
 
     let battery_char_uuid = match Uuid::parse_str(BATTERY_LEVEL_UUID) {
         Ok(uuid) => uuid,
-        Err(_) => return,
+        Err(_) => return false,
     };
 
     for service in services {
         if service.uuid == battery_service_uuid {
             for characteristic in &service.characteristics {
                 if characteristic.uuid == battery_char_uuid
-                    && characteristic
-                        .properties
-                        .contains(btleplug::api::CharPropFlags::READ)
+                    && characteristic.properties.contains(CharPropFlags::READ)
                 {
-                    match peripheral.read(&characteristic).await {
+                    match peripheral.read(characteristic).await {
                         Ok(data) => {
-                            if !data.is_empty() {
-                                println!("🔋 Battery Level: {}%", data[0]);
+                            if let Some(reading) = decode_battery(&data) {
+                                forward_reading(reading, now_micros(), buttons, events, device);
+                                return true;
                             }
                         }
                         Err(e) => {
-                            println!("Could not read battery level: {}", e);
+                            eprintln!("Could not read battery level: {}", e);
                         }
                     }
-                    return;
+                    return false;
                 }
             }
-            return;
+            return false;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BUTTON_STATE_UUID;
+    use crate::event::CONNECTION_BUTTON;
+    use crate::fake::{FakeDevice, FakeScanner};
+    use tokio::sync::mpsc;
+
+    const SENSOR_SERVICE_UUID: &str = "EF680900-9B35-4933-9B10-52FFA9740042";
+    const SENSOR_CHAR_UUID: &str = "EF680901-9B35-4933-9B10-52FFA9740042";
+    /// Service UUIDs lgrcp-embed advertises: the button service in the advertising data,
+    /// then the 16-bit battery (0x180F) and 0x0800 UUIDs from its scan response.
+    const FIRMWARE_ADVERTISED: [&str; 3] = [
+        BUTTON_SERVICE_UUID,
+        "0000180F-0000-1000-8000-00805F9B34FB",
+        "00000800-0000-1000-8000-00805F9B34FB",
+    ];
+
+    #[tokio::test(start_paused = true)]
+    async fn forwards_notifications_from_the_discovered_device() {
+        let other = FakeDevice::new("11:11:11:11:11:11", "Headphones");
+        // Matches by name and has the button service, but is filtered out by the scan as it
+        // only advertises 16-bit UUIDs, like firmware built before the service was advertised.
+        let unfiltered = FakeDevice::new("22:22:22:22:22:22", DEVICE_NAME)
+            .advertising(&FIRMWARE_ADVERTISED[1..])
+            .with_service(
+                BUTTON_SERVICE_UUID,
+                &[(BUTTON_STATE_UUID, CharPropFlags::NOTIFY)],
+            );
+        let microbit = FakeDevice::new("AA:BB:CC:DD:EE:FF", DEVICE_NAME)
+            .advertising(&FIRMWARE_ADVERTISED)
+            .with_service(
+                BUTTON_SERVICE_UUID,
+                &[
+                    (BUTTON_STATE_UUID, CharPropFlags::NOTIFY),
+                    (
+                        COMMAND_CHAR_UUID,
+                        CharPropFlags::WRITE | CharPropFlags::NOTIFY,
+                    ),
+                ],
+            )
+            .with_service(
+                BATTERY_SERVICE_UUID,
+                &[(
+                    BATTERY_LEVEL_UUID,
+                    CharPropFlags::READ | CharPropFlags::NOTIFY,
+                )],
+            )
+            .with_service(
+                SENSOR_SERVICE_UUID,
+                &[(SENSOR_CHAR_UUID, CharPropFlags::NOTIFY)],
+            )
+            .with_value(BATTERY_LEVEL_UUID, &[92])
+            .notify(BUTTON_STATE_UUID, &[1, 0x10, 0x27, 0, 0])
+            .notify(BATTERY_LEVEL_UUID, &[2])
+            .notify(BUTTON_STATE_UUID, &[0, 0xF4, 0x29, 0, 0]);
+        let scanner = FakeScanner::new(vec![other, unfiltered, microbit.clone()]);
+
+        let filter = DeviceFilter {
+            name_prefix: Some(DEVICE_NAME.to_string()),
+            ..DeviceFilter::default()
+        };
+        let mut found = Vec::new();
+        discover_devices(&scanner, &filter, Duration::from_secs(10), |device| {
+            found.push(device)
+        })
+        .await
+        .unwrap();
+        assert_eq!(found.len(), 1);
+        let device = found.remove(0);
+        assert_eq!(device.address(), "AA:BB:CC:DD:EE:FF");
+
+        let (events, mut received) = mpsc::unbounded_channel();
+        let disconnections = scanner.disconnections().await.unwrap();
+        let stopped = connect_and_listen(&device, None, &events, disconnections)
+            .await
+            .unwrap();
+        assert_eq!(stopped, Stopped::Disconnected);
+        let subscribed = microbit.subscribed();
+        assert!(subscribed.contains(&Uuid::parse_str(BUTTON_STATE_UUID).unwrap()));
+        assert!(subscribed.contains(&Uuid::parse_str(BATTERY_LEVEL_UUID).unwrap()));
+        assert!(!subscribed.contains(&Uuid::parse_str(SENSOR_CHAR_UUID).unwrap()));
+
+        let connected = received.recv().await.unwrap();
+        assert_eq!(connected.state, "CONNECTED");
+        let battery = received.recv().await.unwrap();
+        assert_eq!(battery.battery, Some(92));
+        let pressed = received.recv().await.unwrap();
+        assert_eq!(
+            (pressed.button.as_str(), pressed.state.as_str()),
+            ("A", "PRESSED")
+        );
+        assert_eq!(pressed.device.as_deref(), Some("AA:BB:CC:DD:EE:FF"));
+        assert_eq!(pressed.trace.unwrap().device_tick, Some(10_000));
+        let battery = received.recv().await.unwrap();
+        assert_eq!(
+            (battery.button.as_str(), battery.battery),
+            (BATTERY_BUTTON, Some(2))
+        );
+        let released = received.recv().await.unwrap();
+        assert_eq!(
+            (released.button.as_str(), released.state.as_str()),
+            ("A", "RELEASED")
+        );
+        assert_eq!(released.duration_ms, Some(740));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_devices_send_heartbeats() {
+        let with_battery = FakeDevice::new("AA:BB:CC:DD:EE:FF", DEVICE_NAME)
+            .with_service(
+                BUTTON_SERVICE_UUID,
+                &[(BUTTON_STATE_UUID, CharPropFlags::NOTIFY)],
+            )
+            .with_service(
+                BATTERY_SERVICE_UUID,
+                &[(BATTERY_LEVEL_UUID, CharPropFlags::READ)],
+            )
+            .with_value(BATTERY_LEVEL_UUID, &[92])
+            .staying_in_range();
+        let without_battery = FakeDevice::new("11:22:33:44:55:66", DEVICE_NAME)
+            .with_service(
+                BUTTON_SERVICE_UUID,
+                &[(BUTTON_STATE_UUID, CharPropFlags::NOTIFY)],
+            )
+            .staying_in_range();
+        let scanner = FakeScanner::new(vec![with_battery.clone(), without_battery.clone()]);
+
+        for (device, heartbeat) in [
+            (with_battery, (BATTERY_BUTTON, Some(92))),
+            (without_battery, (CONNECTION_BUTTON, None)),
+        ] {
+            let (events, mut received) = mpsc::unbounded_channel();
+            let disconnections = scanner.disconnections().await.unwrap();
+            let listening = tokio::spawn(async move {
+                connect_and_listen(&device, None, &events, disconnections).await
+            });
+
+            let connected = received.recv().await.unwrap();
+            assert_eq!(connected.state, "CONNECTED");
+            if heartbeat.1.is_some() {
+                received.recv().await.unwrap();
+            }
+            let started = time::Instant::now();
+            let beat = received.recv().await.unwrap();
+            assert_eq!((beat.button.as_str(), beat.battery), heartbeat);
+            assert_eq!(
+                started.elapsed(),
+                Duration::from_millis(HEARTBEAT_INTERVAL_MS)
+            );
+            listening.abort();
         }
     }
 }
@@ -0,0 +1,87 @@
+/// A button that is currently held.
+struct Held {
+    button: &'static str,
+    device_tick: Option<u32>,
+    /// When the press was received, in microseconds since the Unix epoch.
+    received_at: u64,
+}
+
+/// A release attributed to the button it belongs to.
+#[derive(Debug, PartialEq)]
+pub struct Release {
+    pub button: &'static str,
+    /// How long the button was held, in milliseconds.
+    pub duration_ms: u64,
+}
+
+/// Tracks which buttons of one device are held, so releases can be paired with their
+/// presses. The firmware notifies the same `0` state for every release, and the state
+/// characteristic only reads `0` when no button is held, so a release ends every press
+/// still open, in the order they started.
+#[derive(Default)]
+pub struct ButtonTracker {
+    held: Vec<Held>,
+}
+
+impl ButtonTracker {
+    pub fn press(&mut self, button: &'static str, device_tick: Option<u32>, received_at: u64) {
+        if let Some(index) = self.held.iter().position(|held| held.button == button) {
+            // The release in between was lost; time the button from its latest press.
+            self.held.remove(index);
+        }
+        self.held.push(Held {
+            button,
+            device_tick,
+            received_at,
+        });
+    }
+
+    /// Releases every held button. Durations come from the device's tick count when both
+    /// notifications carry one, so they do not include Bluetooth latency, and from the
+    /// listener's receive times otherwise. Returns nothing if no press was seen, for
+    /// example when the device was connected while a button was held.
+    pub fn release(&mut self, device_tick: Option<u32>, received_at: u64) -> Vec<Release> {
+        self.held
+            .drain(..)
+            .map(|held| {
+                let duration_ms = match (held.device_tick, device_tick) {
+                    (Some(pressed), Some(released)) => released.wrapping_sub(pressed) as u64,
+                    _ => received_at.saturating_sub(held.received_at) / 1000,
+                };
+                Release {
+                    button: held.button,
+                    duration_ms,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_every_held_button_with_its_duration() {
+        let mut buttons = ButtonTracker::default();
+        assert!(buttons.release(Some(100), 0).is_empty());
+
+        buttons.press("A", Some(1_000), 5_000_000);
+        buttons.press("B", None, 5_200_000);
+        buttons.press("A", Some(1_100), 5_300_000);
+        assert_eq!(
+            buttons.release(Some(1_600), 5_650_000),
+            [
+                Release {
+                    button: "B",
+                    duration_ms: 450
+                },
+                Release {
+                    button: "A",
+                    duration_ms: 500
+                },
+            ]
+        );
+        assert!(buttons.release(Some(1_700), 5_750_000).is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use reqwest::Url;
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::bluetooth::DeviceFilter;
+use crate::config::{
+    ADAPTER_ENV, DEVICE_ADDRESSES_ENV, DEVICE_PREFIX_ENV, DEVICE_SERVICE_ENV, EVENTS_PATH,
+    FILE_SINK_KEEP, FILE_SINK_KEEP_ENV, FILE_SINK_MAX_BYTES, FILE_SINK_MAX_BYTES_ENV,
+    INGEST_TOKEN_ENV, OUTPUT_FORMAT_ENV, SCAN_TIMEOUT_ENV, SCAN_TIMEOUT_MS, SERVER_URL,
+    SERVER_URL_ENV, SINKS_ENV, SPOOL_DROP_POLICY_ENV, SPOOL_MAX_AGE_ENV, SPOOL_MAX_AGE_SECS,
+    SPOOL_MAX_EVENTS, SPOOL_MAX_EVENTS_ENV, SPOOL_PATH, SPOOL_PATH_ENV, STREAM_PATH,
+};
+use crate::sinks::SinkSpec;
+use crate::spool::{DropPolicy, SpoolLimits};
+
+/// Forwards micro:bit button events from BLE to ws-server and other sinks, and inspects BLE
+/// devices.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten, next_help_heading = "Devices")]
+    pub devices: DeviceArgs,
+    #[command(flatten, next_help_heading = "Sinks")]
+    pub sinks: SinkArgs,
+    #[command(flatten, next_help_heading = "Server")]
+    pub server: ServerArgs,
+    #[command(flatten, next_help_heading = "Spool")]
+    pub spool: SpoolArgs,
+    /// How `scan`, `info`, `read` and `write` print their results.
+    #[arg(long, global = true, env = OUTPUT_FORMAT_ENV, value_enum, default_value_t = Format::Text, help_heading = "Output")]
+    pub format: Format,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Keep every matching device connected and send its events to the sinks (default).
+    Monitor,
+    /// List nearby devices with their signal strength.
+    Scan,
+    /// Connect to a device and dump its GATT table.
+    Info {
+        /// Address or advertised name of the device.
+        device: String,
+    },
+    /// Read a characteristic of the first matching device.
+    Read { characteristic: Uuid },
+    /// Write bytes to a characteristic of the first matching device.
+    Write {
+        characteristic: Uuid,
+        /// Bytes in hex, e.g. `01ff` or `0x01FF`.
+        value: HexBytes,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct DeviceArgs {
+    /// Use devices whose advertised name starts with this prefix [default without other
+    /// criteria: LGR-BLE]
+    #[arg(long, global = true, env = DEVICE_PREFIX_ENV)]
+    pub device_name: Option<String>,
+    /// Use the devices with these addresses, comma-separated.
+    #[arg(long = "address", value_name = "ADDRESS", global = true, env = DEVICE_ADDRESSES_ENV, value_delimiter = ',')]
+    pub addresses: Vec<String>,
+    /// Use devices advertising this service UUID, and filter scans on it.
+    #[arg(long, global = true, env = DEVICE_SERVICE_ENV)]
+    pub service: Option<Uuid>,
+    /// Bluetooth adapter, e.g. `hci0` [default: the first one]
+    #[arg(long, global = true, env = ADAPTER_ENV)]
+    pub adapter: Option<String>,
+    /// How long each scan round listens for devices, in milliseconds.
+    #[arg(long, global = true, env = SCAN_TIMEOUT_ENV, default_value_t = SCAN_TIMEOUT_MS)]
+    pub scan_timeout_ms: u64,
+}
+
+impl DeviceArgs {
+    pub fn filter(&self) -> DeviceFilter {
+        DeviceFilter::new(
+            self.device_name.clone(),
+            self.addresses.clone(),
+            self.service,
+        )
+    }
+
+    pub fn scan_timeout(&self) -> Duration {
+        Duration::from_millis(self.scan_timeout_ms)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SinkArgs {
+    /// Where `monitor` sends events; repeat or comma-separate for several: `server`,
+    /// `stdout`, `file=<path>`, `http=<url>` or `mqtt=<url>`.
+    #[arg(long = "sink", value_name = "SINK", global = true, env = SINKS_ENV, value_delimiter = ',', default_value = "server")]
+    pub sinks: Vec<SinkSpec>,
+    /// Size at which file sinks are rotated, in bytes.
+    #[arg(long, global = true, env = FILE_SINK_MAX_BYTES_ENV, default_value_t = FILE_SINK_MAX_BYTES)]
+    pub file_max_bytes: u64,
+    /// How many rotated files each file sink keeps.
+    #[arg(long, global = true, env = FILE_SINK_KEEP_ENV, default_value_t = FILE_SINK_KEEP)]
+    pub file_keep: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ServerArgs {
+    /// Base URL of ws-server.
+    #[arg(long = "server", global = true, env = SERVER_URL_ENV, default_value = SERVER_URL)]
+    pub url: Url,
+    /// Bearer token, if ws-server requires one for ingest.
+    #[arg(long, global = true, env = INGEST_TOKEN_ENV, hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+impl ServerArgs {
+    pub fn base_url(&self) -> String {
+        self.url.as_str().trim_end_matches('/').to_string()
+    }
+
+    /// Where events are POSTed while the stream is down.
+    pub fn events_url(&self) -> String {
+        format!("{}{}", self.base_url(), EVENTS_PATH)
+    }
+
+    /// The event stream, over `wss` if the server is reached over `https`.
+    pub fn stream_url(&self) -> String {
+        let scheme = if self.url.scheme() == "https" {
+            "wss"
+        } else {
+            "ws"
+        };
+        let base = self.base_url();
+        let rest = base
+            .split_once("://")
+            .map_or(base.as_str(), |(_, rest)| rest);
+        format!("{}://{}{}", scheme, rest, STREAM_PATH)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SpoolArgs {
+    /// Log file of events not yet delivered to ws-server.
+    #[arg(long = "spool", global = true, env = SPOOL_PATH_ENV, default_value = SPOOL_PATH)]
+    pub path: PathBuf,
+    /// Most events the spool keeps.
+    #[arg(long = "spool-max-events", global = true, env = SPOOL_MAX_EVENTS_ENV, default_value_t = SPOOL_MAX_EVENTS)]
+    pub max_events: usize,
+    /// Spooled events older than this are discarded; 0 keeps them until delivered.
+    #[arg(long = "spool-max-age-secs", global = true, env = SPOOL_MAX_AGE_ENV, default_value_t = SPOOL_MAX_AGE_SECS)]
+    pub max_age_secs: u64,
+    /// Which event to drop when the spool is full: `oldest` or `newest`.
+    #[arg(long = "spool-drop-policy", global = true, env = SPOOL_DROP_POLICY_ENV, default_value = "oldest")]
+    pub drop_policy: DropPolicy,
+}
+
+impl SpoolArgs {
+    pub fn limits(&self) -> SpoolLimits {
+        SpoolLimits {
+            max_events: self.max_events,
+            max_age: (self.max_age_secs > 0).then(|| Duration::from_secs(self.max_age_secs)),
+            drop_policy: self.drop_policy,
+        }
+    }
+}
+
+/// Bytes given in hex on the command line.
+#[derive(Clone, Debug)]
+pub struct HexBytes(pub Vec<u8>);
+
+impl std::str::FromStr for HexBytes {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let digits = value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+            .unwrap_or(value);
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err("expected an even number of hex digits".to_string());
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| {
+                digits
+                    .get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| format!("invalid hex byte at position {}", i))
+            })
+            .collect::<Result<_, _>>()
+            .map(HexBytes)
+    }
+}
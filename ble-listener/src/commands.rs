@@ -0,0 +1,199 @@
+use btleplug::api::{Characteristic, WriteType};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+
+use crate::transport::Device;
+
+/// ws-server's command endpoints.
+#[derive(Clone)]
+pub struct CommandServer {
+    pub client: Client,
+    /// Base URL of ws-server.
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CommandKind {
+    ShowText { text: String },
+    PlayTone { frequency: u16, duration_ms: u16 },
+    ResetCounters,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Command {
+    pub id: u64,
+    #[serde(flatten)]
+    pub command: CommandKind,
+}
+
+#[derive(Debug, Serialize)]
+struct CommandReport<'a> {
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A command written to the device, waiting for the device to confirm it ran.
+#[derive(Debug)]
+pub struct Sent {
+    id: u64,
+    opcode: u8,
+}
+
+/// Encodes a command for the device's command characteristic: one opcode byte followed
+/// by the little-endian arguments.
+pub fn encode(command: &CommandKind) -> Vec<u8> {
+    match command {
+        CommandKind::ShowText { text } => {
+            let mut bytes = vec![0x01];
+            bytes.extend_from_slice(text.as_bytes());
+            bytes
+        }
+        CommandKind::PlayTone {
+            frequency,
+            duration_ms,
+        } => {
+            let mut bytes = vec![0x02];
+            bytes.extend_from_slice(&frequency.to_le_bytes());
+            bytes.extend_from_slice(&duration_ms.to_le_bytes());
+            bytes
+        }
+        CommandKind::ResetCounters => vec![0x03],
+    }
+}
+
+async fn fetch_pending(
+    server: &CommandServer,
+    device: &str,
+) -> Result<Vec<Command>, Box<dyn Error>> {
+    let url = format!("{}/api/devices/{}/commands/pull", server.url, device);
+    let commands = server
+        .client
+        .post(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(commands)
+}
+
+async fn report(server: &CommandServer, id: u64, status: &str, error: Option<String>) {
+    let url = format!("{}/api/commands/{}/status", server.url, id);
+    let body = CommandReport { status, error };
+    match server.client.post(url).json(&body).send().await {
+        Ok(response) if !response.status().is_success() => {
+            eprintln!(
+                "❌ Failed to report command {}: HTTP {}",
+                id,
+                response.status()
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("❌ Network error reporting command {}: {}", id, e);
+        }
+    }
+}
+
+/// Pulls queued commands for `device` from the server and writes them to the device. A
+/// command the device refuses is reported as failed; the others wait in `awaiting` until
+/// the device confirms them, see [`confirm`].
+pub async fn deliver_pending<D: Device>(
+    peripheral: &D,
+    server: &CommandServer,
+    device: &str,
+    characteristic: Option<&Characteristic>,
+    awaiting: &mut VecDeque<Sent>,
+) {
+    let commands = match fetch_pending(server, device).await {
+        Ok(commands) => commands,
+        Err(e) => {
+            eprintln!("❌ Failed to fetch commands: {}", e);
+            return;
+        }
+    };
+
+    for command in commands {
+        eprintln!("📥 Command {}: {:?}", command.id, command.command);
+
+        let Some(characteristic) = characteristic else {
+            let error = "device does not expose the command characteristic".to_string();
+            report(server, command.id, "failed", Some(error)).await;
+            continue;
+        };
+
+        let payload = encode(&command.command);
+        match peripheral
+            .write(characteristic, &payload, WriteType::WithResponse)
+            .await
+        {
+            Ok(()) => awaiting.push_back(Sent {
+                id: command.id,
+                opcode: payload[0],
+            }),
+            Err(e) => report(server, command.id, "failed", Some(e.to_string())).await,
+        }
+    }
+}
+
+/// Decodes a confirmation notified on the command characteristic: the opcode of the
+/// command that ran, followed by a status byte that is `0` on success.
+fn decode_confirmation(value: &[u8]) -> Option<(u8, Result<(), String>)> {
+    match value {
+        [opcode, 0] => Some((*opcode, Ok(()))),
+        [opcode, status] => Some((*opcode, Err(format!("device error {:#04x}", status)))),
+        _ => None,
+    }
+}
+
+/// Reports the outcome of the oldest command in `awaiting`. The device runs commands one
+/// at a time in the order they were written, so confirmations arrive in that order too.
+pub async fn confirm(server: &CommandServer, awaiting: &mut VecDeque<Sent>, value: &[u8]) {
+    let Some((opcode, outcome)) = decode_confirmation(value) else {
+        eprintln!("❌ Malformed command confirmation {:02x?}", value);
+        return;
+    };
+    let Some(sent) = awaiting.pop_front() else {
+        eprintln!(
+            "❌ Confirmation for opcode {:#04x} without a pending command",
+            opcode
+        );
+        return;
+    };
+    if sent.opcode != opcode {
+        eprintln!(
+            "❌ Command {} has opcode {:#04x}, but the device confirmed {:#04x}",
+            sent.id, sent.opcode, opcode
+        );
+    }
+    match outcome {
+        Ok(()) => {
+            eprintln!("✅ Command {} ran on the device", sent.id);
+            report(server, sent.id, "acknowledged", None).await;
+        }
+        Err(error) => report(server, sent.id, "failed", Some(error)).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_confirmations() {
+        let text = encode(&CommandKind::ShowText {
+            text: "HI".to_string(),
+        });
+        assert_eq!(text, [0x01, b'H', b'I']);
+        assert_eq!(decode_confirmation(&[0x01, 0x00]), Some((0x01, Ok(()))));
+        assert_eq!(
+            decode_confirmation(&[0x02, 0x03]),
+            Some((0x02, Err("device error 0x03".to_string())))
+        );
+        assert_eq!(decode_confirmation(&[0x03]), None);
+    }
+}
@@ -1,4 +1,64 @@
 pub const BATTERY_SERVICE_UUID: &str = "0000180F-0000-1000-8000-00805F9B34FB";
 pub const BATTERY_LEVEL_UUID: &str = "00002A19-0000-1000-8000-00805F9B34FB";
+/// Default name prefix of the devices to connect to.
 pub const DEVICE_NAME: &str = "LGR-BLE";
-pub const WEB_SERVER_URL: &str = "http://0.0.0.0:3000/api/button";
+/// Service the firmware advertises; scans are filtered on it unless another is configured.
+pub const BUTTON_SERVICE_UUID: &str = "EF680800-9B35-4933-9B10-52FFA9740042";
+/// Button state characteristic of the button service.
+pub const BUTTON_STATE_UUID: &str = "EF680801-9B35-4933-9B10-52FFA9740042";
+/// How long each scan round listens for devices.
+pub const SCAN_TIMEOUT_MS: u64 = 10_000;
+pub const SCAN_TIMEOUT_ENV: &str = "BLE_SCAN_TIMEOUT_MS";
+/// Connect to devices whose advertised name starts with this prefix.
+pub const DEVICE_PREFIX_ENV: &str = "BLE_DEVICE_PREFIX";
+/// Connect to the devices with these comma-separated addresses.
+pub const DEVICE_ADDRESSES_ENV: &str = "BLE_DEVICE_ADDRESSES";
+/// Connect to devices advertising this service UUID, and filter scans on it.
+pub const DEVICE_SERVICE_ENV: &str = "BLE_DEVICE_SERVICE";
+/// Events are POSTed here, relative to the server URL, while the stream is down.
+pub const EVENTS_PATH: &str = "/api/button";
+/// WebSocket events are streamed to, relative to the server URL.
+pub const STREAM_PATH: &str = "/api/button/stream";
+/// How long the server may take to acknowledge a streamed event before the stream is
+/// considered lost.
+pub const ACK_TIMEOUT_MS: u64 = 5000;
+/// Base URL of ws-server.
+pub const SERVER_URL: &str = "http://0.0.0.0:3000";
+pub const SERVER_URL_ENV: &str = "BLE_SERVER_URL";
+/// Name of the Bluetooth adapter to use, e.g. `hci0`; the first one if unset.
+pub const ADAPTER_ENV: &str = "BLE_ADAPTER";
+/// `text` or `json`: how inspection commands print their results.
+pub const OUTPUT_FORMAT_ENV: &str = "BLE_OUTPUT_FORMAT";
+pub const COMMAND_CHAR_UUID: &str = "EF680802-9B35-4933-9B10-52FFA9740042";
+pub const COMMAND_POLL_INTERVAL_MS: u64 = 1000;
+/// How often a connected device's battery level is read again, so ws-server keeps
+/// hearing from devices whose buttons are not pressed.
+pub const HEARTBEAT_INTERVAL_MS: u64 = 60_000;
+/// Append-only log of events not yet delivered to ws-server.
+pub const SPOOL_PATH: &str = "ble-listener-spool.ndjson";
+pub const SPOOL_PATH_ENV: &str = "BLE_SPOOL_PATH";
+/// Most events the spool keeps; when full, the drop policy decides which event is lost.
+pub const SPOOL_MAX_EVENTS: usize = 10_000;
+pub const SPOOL_MAX_EVENTS_ENV: &str = "BLE_SPOOL_MAX_EVENTS";
+/// Spooled events older than this are discarded; 0 keeps them until delivered.
+pub const SPOOL_MAX_AGE_SECS: u64 = 24 * 60 * 60;
+pub const SPOOL_MAX_AGE_ENV: &str = "BLE_SPOOL_MAX_AGE_SECS";
+/// `oldest` or `newest`: which event to drop when the spool is full.
+pub const SPOOL_DROP_POLICY_ENV: &str = "BLE_SPOOL_DROP_POLICY";
+/// Where `monitor` sends events, comma-separated; see `SinkSpec`.
+pub const SINKS_ENV: &str = "BLE_SINKS";
+/// Size at which a file sink is rotated.
+pub const FILE_SINK_MAX_BYTES: u64 = 10 * 1024 * 1024;
+pub const FILE_SINK_MAX_BYTES_ENV: &str = "BLE_FILE_SINK_MAX_BYTES";
+/// How many rotated files a file sink keeps.
+pub const FILE_SINK_KEEP: usize = 5;
+pub const FILE_SINK_KEEP_ENV: &str = "BLE_FILE_SINK_KEEP";
+pub const MQTT_DEFAULT_PORT: u16 = 1883;
+/// Topic prefix for MQTT sinks whose URL has no path; events go to `<topic>/<device>`.
+pub const MQTT_DEFAULT_TOPIC: &str = "ble-listener/events";
+/// Delay before the first reconnection attempt; it doubles after every failed attempt.
+pub const RECONNECT_INITIAL_DELAY_MS: u64 = 1000;
+/// Upper bound for the reconnection delay.
+pub const RECONNECT_MAX_DELAY_MS: u64 = 60_000;
+/// Bearer token sent to ws-server when it requires one for ingest.
+pub const INGEST_TOKEN_ENV: &str = "WS_INGEST_TOKEN";
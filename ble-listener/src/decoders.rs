@@ -0,0 +1,136 @@
+use uuid::Uuid;
+
+use crate::config::{BATTERY_LEVEL_UUID, BUTTON_STATE_UUID};
+
+/// What a notification from a known characteristic means.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reading {
+    Button {
+        button: &'static str,
+        state: &'static str,
+        /// The device's uptime in milliseconds when the button changed.
+        device_tick: Option<u32>,
+    },
+    /// Battery level in percent.
+    Battery(u8),
+}
+
+/// Turns a characteristic's payload into a reading, or `None` if it cannot be decoded.
+pub type Decoder = fn(&[u8]) -> Option<Reading>;
+
+/// The characteristics the listener subscribes to. Others are never decoded; to support a
+/// new sensor, add its characteristic here with a decoder and a `Reading` variant.
+const KNOWN_CHARACTERISTICS: &[(&str, Decoder)] = &[
+    (BUTTON_STATE_UUID, decode_button),
+    (BATTERY_LEVEL_UUID, decode_battery),
+];
+
+pub fn decoder_for(characteristic: Uuid) -> Option<Decoder> {
+    KNOWN_CHARACTERISTICS
+        .iter()
+        .find(|(uuid, _)| Uuid::parse_str(uuid).is_ok_and(|uuid| uuid == characteristic))
+        .map(|(_, decoder)| *decoder)
+}
+
+/// Button state: one state byte, followed by the firmware's uptime in milliseconds as a
+/// little-endian u32.
+pub fn decode_button(data: &[u8]) -> Option<Reading> {
+    let Some(&value) = data.first() else {
+        eprintln!("Received empty button notification");
+        return None;
+    };
+    let device_tick = data
+        .get(1..5)
+        .and_then(|tick| tick.try_into().ok())
+        .map(u32::from_le_bytes);
+
+    let (button, state) = match value {
+        1 => {
+            eprintln!("🔴 Button A (LEFT) PRESSED");
+            ("A", "PRESSED")
+        }
+        2 => {
+            eprintln!("🔵 Button B (RIGHT) PRESSED");
+            ("B", "PRESSED")
+        }
+        0 => {
+            eprintln!("⚪ Button RELEASED");
+            ("ANY", "RELEASED")
+        }
+        _ => {
+            eprintln!("Unknown button value: {}", value);
+            return None;
+        }
+    };
+    Some(Reading::Button {
+        button,
+        state,
+        device_tick,
+    })
+}
+
+/// Battery Level (0x2A19): a single byte in percent.
+pub fn decode_battery(data: &[u8]) -> Option<Reading> {
+    match data.first() {
+        Some(&level) if level <= 100 => {
+            eprintln!("🔋 Battery Level: {}%", level);
+            Some(Reading::Battery(level))
+        }
+        Some(&level) => {
+            eprintln!("Invalid battery level: {}", level);
+            None
+        }
+        None => {
+            eprintln!("Received empty battery level");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn button(button: &'static str, state: &'static str, device_tick: Option<u32>) -> Reading {
+        Reading::Button {
+            button,
+            state,
+            device_tick,
+        }
+    }
+
+    #[test]
+    fn decodes_button_states_with_and_without_a_tick() {
+        assert_eq!(
+            decode_button(&[1, 0x10, 0x27, 0, 0]),
+            Some(button("A", "PRESSED", Some(10_000)))
+        );
+        assert_eq!(decode_button(&[2]), Some(button("B", "PRESSED", None)));
+        // A truncated tick is dropped rather than misread.
+        assert_eq!(
+            decode_button(&[0, 0x10, 0x27]),
+            Some(button("ANY", "RELEASED", None))
+        );
+        assert_eq!(decode_button(&[3, 0, 0, 0, 0]), None);
+        assert_eq!(decode_button(&[]), None);
+    }
+
+    #[test]
+    fn decodes_battery_levels_up_to_100() {
+        assert_eq!(decode_battery(&[0]), Some(Reading::Battery(0)));
+        assert_eq!(decode_battery(&[100]), Some(Reading::Battery(100)));
+        assert_eq!(decode_battery(&[101]), None);
+        assert_eq!(decode_battery(&[255]), None);
+        assert_eq!(decode_battery(&[]), None);
+    }
+
+    #[test]
+    fn finds_decoders_only_for_known_characteristics() {
+        let battery = Uuid::parse_str(BATTERY_LEVEL_UUID).unwrap();
+        assert_eq!(
+            decoder_for(battery).unwrap()(&[42]),
+            Some(Reading::Battery(42))
+        );
+        assert!(decoder_for(Uuid::nil()).is_none());
+    }
+}
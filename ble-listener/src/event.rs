@@ -1,38 +1,120 @@
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::config::WEB_SERVER_URL;
+/// Button name ws-server uses for connection changes (`CONNECTED` / `DISCONNECTED`).
+pub const CONNECTION_BUTTON: &str = "CONNECTION";
+/// Button name ws-server uses for battery readings, sent with state `REPORTED`.
+pub const BATTERY_BUTTON: &str = "BATTERY";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ButtonEvent {
     pub button: String,
     pub state: String,
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<u8>,
+    /// For releases, how long the button was held, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Trace>,
 }
 
-pub async fn send_button_event(client: &Client, button: &str, state: &str) {
-    let timestamp = SystemTime::now()
+/// Per-hop timestamps used by ws-server to compute latency. Host times are microseconds
+/// since the Unix epoch; `device_tick` is the device's uptime in milliseconds.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Trace {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_tick: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener_received_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener_sent_at: Option<u64>,
+}
+
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as u64
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_millis() as u64;
+        .as_millis() as u64
+}
 
-    let event = ButtonEvent {
-        button: button.to_string(),
+/// Event telling ws-server that `device` connected or disconnected.
+pub fn connection_event(device: &str, state: &str) -> ButtonEvent {
+    ButtonEvent {
+        button: CONNECTION_BUTTON.to_string(),
         state: state.to_string(),
-        timestamp,
-    };
+        timestamp: now_millis(),
+        device: Some(device.to_string()),
+        battery: None,
+        duration_ms: None,
+        trace: None,
+    }
+}
 
-    match client.post(WEB_SERVER_URL).json(&event).send().await {
+/// Whether the server refused the event itself, so sending it again cannot succeed.
+fn is_malformed(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY
+    )
+}
+
+/// Posts one event to `url`. Returns `false` if it should be sent again later. Only an
+/// event the server rejects as malformed (`400` or `422`) counts as handled without being
+/// delivered: any other error, including a missing or wrong token, can clear up once the
+/// server or its configuration is fixed.
+pub async fn send_button_event(client: &Client, url: &str, mut event: ButtonEvent) -> bool {
+    if let Some(trace) = event.trace.as_mut() {
+        trace.listener_sent_at = Some(now_micros());
+    }
+
+    match client.post(url).json(&event).send().await {
         Ok(response) => {
-            if response.status().is_success() {
-                println!("📤 Sent {} {} to web server", button, state);
+            let status = response.status();
+            if status.is_success() {
+                eprintln!("📤 Sent {} {} to web server", event.button, event.state);
+                true
             } else {
-                println!("❌ Failed to send event: HTTP {}", response.status());
+                eprintln!("❌ Failed to send event: HTTP {}", status);
+                is_malformed(status)
             }
         }
         Err(e) => {
-            println!("❌ Network error sending event: {}", e);
+            eprintln!("❌ Network error sending event: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_only_malformed_events() {
+        for status in [StatusCode::BAD_REQUEST, StatusCode::UNPROCESSABLE_ENTITY] {
+            assert!(is_malformed(status));
+        }
+        for status in [
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(!is_malformed(status));
         }
     }
 }
@@ -0,0 +1,261 @@
+use async_trait::async_trait;
+use btleplug::api::{CharPropFlags, Characteristic, Service, ValueNotification, WriteType};
+use btleplug::{Error, Result};
+use futures::stream::StreamExt;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::transport::{
+    Advertisement, Device, Disconnections, Discoveries, Notifications, Scanner,
+};
+
+#[derive(Default)]
+struct State {
+    connected: bool,
+    subscribed: HashSet<Uuid>,
+}
+
+/// In-memory peripheral with a fixed GATT table that plays back a scripted list of
+/// notifications once subscribed, then behaves as if it went out of range unless told to
+/// stay in range. Like a real
+/// device, it advertises only the service UUIDs it is given, whatever its GATT table holds.
+#[derive(Clone)]
+pub struct FakeDevice {
+    address: String,
+    name: Option<String>,
+    advertised: Vec<Uuid>,
+    services: BTreeSet<Service>,
+    values: Vec<(Uuid, Vec<u8>)>,
+    script: Vec<(Uuid, Vec<u8>)>,
+    stays_in_range: bool,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeDevice {
+    pub fn new(address: &str, name: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            name: Some(name.to_string()),
+            advertised: Vec::new(),
+            services: BTreeSet::new(),
+            values: Vec::new(),
+            script: Vec::new(),
+            stays_in_range: false,
+            state: Arc::default(),
+        }
+    }
+
+    /// Sets the service UUIDs listed in the advertisement and scan response.
+    pub fn advertising(mut self, services: &[&str]) -> Self {
+        self.advertised = services
+            .iter()
+            .map(|service| Uuid::parse_str(service).unwrap())
+            .collect();
+        self
+    }
+
+    /// Adds a service exposing `characteristics` with the given properties.
+    pub fn with_service(
+        mut self,
+        service: &str,
+        characteristics: &[(&str, CharPropFlags)],
+    ) -> Self {
+        let service_uuid = Uuid::parse_str(service).unwrap();
+        self.services.insert(Service {
+            uuid: service_uuid,
+            primary: true,
+            characteristics: characteristics
+                .iter()
+                .map(|(uuid, properties)| Characteristic {
+                    uuid: Uuid::parse_str(uuid).unwrap(),
+                    service_uuid,
+                    properties: *properties,
+                    descriptors: BTreeSet::new(),
+                })
+                .collect(),
+        });
+        self
+    }
+
+    /// Sets the value returned when `characteristic` is read.
+    pub fn with_value(mut self, characteristic: &str, value: &[u8]) -> Self {
+        self.values
+            .push((Uuid::parse_str(characteristic).unwrap(), value.to_vec()));
+        self
+    }
+
+    /// Queues a notification on `characteristic`, delivered if it was subscribed to.
+    pub fn notify(mut self, characteristic: &str, value: &[u8]) -> Self {
+        self.script
+            .push((Uuid::parse_str(characteristic).unwrap(), value.to_vec()));
+        self
+    }
+
+    /// Keeps the notification stream open once the script has played.
+    pub fn staying_in_range(mut self) -> Self {
+        self.stays_in_range = true;
+        self
+    }
+
+    pub fn subscribed(&self) -> HashSet<Uuid> {
+        self.state.lock().unwrap().subscribed.clone()
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.state.lock().unwrap().connected {
+            Ok(())
+        } else {
+            Err(Error::NotConnected)
+        }
+    }
+
+    fn find(&self, characteristic: &Characteristic, property: CharPropFlags) -> Result<()> {
+        self.check_connected()?;
+        let found = self
+            .services
+            .iter()
+            .flat_map(|service| &service.characteristics)
+            .any(|c| c.uuid == characteristic.uuid && c.properties.contains(property));
+        if found {
+            Ok(())
+        } else {
+            Err(Error::NoSuchCharacteristic)
+        }
+    }
+}
+
+#[async_trait]
+impl Device for FakeDevice {
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    async fn advertisement(&self) -> Result<Advertisement> {
+        Ok(Advertisement {
+            name: self.name.clone(),
+            services: self.advertised.clone(),
+            rssi: None,
+        })
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.state.lock().unwrap().connected = true;
+        Ok(())
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.state.lock().unwrap().connected)
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.state.lock().unwrap().connected = false;
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<BTreeSet<Service>> {
+        self.check_connected()?;
+        Ok(self.services.clone())
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.find(characteristic, CharPropFlags::NOTIFY)?;
+        self.state
+            .lock()
+            .unwrap()
+            .subscribed
+            .insert(characteristic.uuid);
+        Ok(())
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.find(characteristic, CharPropFlags::READ)?;
+        self.values
+            .iter()
+            .find(|(uuid, _)| *uuid == characteristic.uuid)
+            .map(|(_, value)| value.clone())
+            .ok_or(Error::NoSuchCharacteristic)
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        _data: &[u8],
+        _write_type: WriteType,
+    ) -> Result<()> {
+        self.find(characteristic, CharPropFlags::WRITE)
+    }
+
+    async fn notifications(&self) -> Result<Notifications> {
+        self.check_connected()?;
+        let subscribed = self.subscribed();
+        let notifications: Vec<ValueNotification> = self
+            .script
+            .iter()
+            .filter(|(uuid, _)| subscribed.contains(uuid))
+            .map(|(uuid, value)| ValueNotification {
+                uuid: *uuid,
+                value: value.clone(),
+            })
+            .collect();
+        let notifications = futures::stream::iter(notifications);
+        if self.stays_in_range {
+            Ok(Box::pin(notifications.chain(futures::stream::pending())))
+        } else {
+            Ok(Box::pin(notifications))
+        }
+    }
+}
+
+/// Adapter that "discovers" a fixed set of fake devices, honouring the scan filter.
+#[derive(Clone)]
+pub struct FakeScanner {
+    devices: Vec<FakeDevice>,
+    services: Arc<Mutex<Vec<Uuid>>>,
+}
+
+impl FakeScanner {
+    pub fn new(devices: Vec<FakeDevice>) -> Self {
+        Self {
+            devices,
+            services: Arc::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Scanner for FakeScanner {
+    type Device = FakeDevice;
+
+    async fn start_scan(&self, services: Vec<Uuid>) -> Result<()> {
+        *self.services.lock().unwrap() = services;
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn discoveries(&self) -> Result<Discoveries<FakeDevice>> {
+        let services = self.services.lock().unwrap().clone();
+        let visible: Vec<FakeDevice> = self
+            .devices
+            .iter()
+            .filter(|device| {
+                services.is_empty()
+                    || device
+                        .advertised
+                        .iter()
+                        .any(|service| services.contains(service))
+            })
+            .cloned()
+            .collect();
+        Ok(Box::pin(
+            futures::stream::iter(visible).chain(futures::stream::pending()),
+        ))
+    }
+
+    async fn disconnections(&self) -> Result<Disconnections> {
+        Ok(Box::pin(futures::stream::pending()))
+    }
+}
@@ -0,0 +1,333 @@
+use btleplug::api::{CharPropFlags, Characteristic, WriteType};
+use futures::stream::StreamExt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+use tokio::time;
+use uuid::Uuid;
+
+use crate::bluetooth::DeviceFilter;
+use crate::cli::{Command, Format};
+use crate::transport::{Device, Scanner};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A device seen while scanning.
+#[derive(Debug, Serialize)]
+pub struct ScanEntry {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub services: Vec<Uuid>,
+    /// Whether the device matches the configured filter.
+    pub matches: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceEntry {
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<CharacteristicEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CharacteristicEntry {
+    pub uuid: Uuid,
+    pub properties: Vec<String>,
+    /// Current value in hex, for readable characteristics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Value {
+    device: String,
+    characteristic: Uuid,
+    value: String,
+}
+
+/// Runs one of the inspection commands against the first device `filter` matches, or, for
+/// `scan`, every device in range. Progress goes to stderr, so stdout only carries the
+/// result in the requested `format`.
+pub async fn run<S: Scanner>(
+    command: Command,
+    scanner: &S,
+    filter: &DeviceFilter,
+    scan_timeout: Duration,
+    format: Format,
+) -> Result<()> {
+    match command {
+        Command::Monitor => unreachable!("monitor is not an inspection command"),
+        Command::Scan => {
+            let entries = scan(scanner, filter, scan_timeout).await?;
+            print(format, &entries, || {
+                for entry in &entries {
+                    let rssi = entry
+                        .rssi
+                        .map_or("   ?".to_string(), |rssi| format!("{:>4}", rssi));
+                    println!(
+                        "{} dBm  {}  {}{}",
+                        rssi,
+                        entry.address,
+                        entry.name.as_deref().unwrap_or("Unknown"),
+                        if entry.matches { "  ✅" } else { "" }
+                    );
+                }
+            })
+        }
+        Command::Info { device } => {
+            let filter = if device.parse::<btleplug::api::BDAddr>().is_ok() {
+                DeviceFilter::new(None, vec![device], None)
+            } else {
+                DeviceFilter::new(Some(device), Vec::new(), None)
+            };
+            let peripheral = find_device(scanner, &filter, scan_timeout).await?;
+            let services = with_connection(&peripheral, gatt_table(&peripheral)).await?;
+            print(format, &services, || {
+                println!("{}", peripheral.address());
+                for service in &services {
+                    println!("  🔹 Service {}", service.uuid);
+                    for characteristic in &service.characteristics {
+                        print!(
+                            "    └─ Characteristic {} ({})",
+                            characteristic.uuid,
+                            characteristic.properties.join(", ")
+                        );
+                        match &characteristic.value {
+                            Some(value) => println!(" = {}", value),
+                            None => println!(),
+                        }
+                    }
+                }
+            })
+        }
+        Command::Read { characteristic } => {
+            let peripheral = find_device(scanner, filter, scan_timeout).await?;
+            let read = async {
+                let found = find_characteristic(&peripheral, characteristic).await?;
+                Ok(peripheral.read(&found).await?)
+            };
+            let value = Value {
+                device: peripheral.address(),
+                characteristic,
+                value: to_hex(&with_connection(&peripheral, read).await?),
+            };
+            print(format, &value, || println!("{}", value.value))
+        }
+        Command::Write {
+            characteristic,
+            value,
+        } => {
+            let peripheral = find_device(scanner, filter, scan_timeout).await?;
+            let write = async {
+                let found = find_characteristic(&peripheral, characteristic).await?;
+                let write_type = if found.properties.contains(CharPropFlags::WRITE) {
+                    WriteType::WithResponse
+                } else {
+                    WriteType::WithoutResponse
+                };
+                Ok(peripheral.write(&found, &value.0, write_type).await?)
+            };
+            with_connection(&peripheral, write).await?;
+            let value = Value {
+                device: peripheral.address(),
+                characteristic,
+                value: to_hex(&value.0),
+            };
+            print(format, &value, || {
+                println!("✅ Wrote {} to {}", value.value, characteristic)
+            })
+        }
+    }
+}
+
+fn print<T: Serialize>(format: Format, result: &T, text: impl FnOnce()) -> Result<()> {
+    match format {
+        Format::Text => text(),
+        Format::Json => println!("{}", serde_json::to_string_pretty(result)?),
+    }
+    Ok(())
+}
+
+/// Scans for `timeout` without a service filter and lists every device seen, strongest
+/// signal first.
+pub async fn scan<S: Scanner>(
+    scanner: &S,
+    filter: &DeviceFilter,
+    timeout: Duration,
+) -> Result<Vec<ScanEntry>> {
+    eprintln!("🔍 Scanning for {:.1}s...", timeout.as_secs_f64());
+    scanner.start_scan(Vec::new()).await?;
+    let mut discoveries = scanner.discoveries().await?;
+    let mut devices = BTreeMap::new();
+    let _ = time::timeout(timeout, async {
+        while let Some(device) = discoveries.next().await {
+            devices.insert(device.address(), device);
+        }
+    })
+    .await;
+    scanner.stop_scan().await?;
+
+    let mut entries = Vec::new();
+    for (address, device) in devices {
+        // Read after the scan, so the RSSI is the latest one.
+        let advertisement = device.advertisement().await?;
+        entries.push(ScanEntry {
+            matches: filter.matches(&address, &advertisement),
+            address,
+            name: advertisement.name,
+            rssi: advertisement.rssi,
+            services: advertisement.services,
+        });
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.rssi));
+    Ok(entries)
+}
+
+/// Scans until a device matching `filter` shows up, for at most `timeout`.
+pub async fn find_device<S: Scanner>(
+    scanner: &S,
+    filter: &DeviceFilter,
+    timeout: Duration,
+) -> Result<S::Device> {
+    eprintln!("🔍 Scanning for devices matching {}...", filter);
+    scanner
+        .start_scan(filter.service.into_iter().collect())
+        .await?;
+    let mut discoveries = scanner.discoveries().await?;
+    let found = time::timeout(timeout, async {
+        while let Some(device) = discoveries.next().await {
+            let address = device.address();
+            match device.advertisement().await {
+                Ok(advertisement) if filter.matches(&address, &advertisement) => {
+                    return Some(device);
+                }
+                _ => {}
+            }
+        }
+        None
+    })
+    .await;
+    scanner.stop_scan().await?;
+    match found {
+        Ok(Some(device)) => {
+            eprintln!("✅ Found device {}", device.address());
+            Ok(device)
+        }
+        _ => Err(format!("No device matching {} found", filter).into()),
+    }
+}
+
+/// Connects, runs `action` and disconnects again, whether it succeeded or not.
+async fn with_connection<D: Device, T>(
+    device: &D,
+    action: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    device.connect().await?;
+    let result = action.await;
+    if let Err(e) = device.disconnect().await {
+        eprintln!("❌ Failed to disconnect from {}: {}", device.address(), e);
+    }
+    result
+}
+
+/// Lists the services of a connected device with their characteristics, reading the
+/// value of every readable one.
+pub async fn gatt_table<D: Device>(device: &D) -> Result<Vec<ServiceEntry>> {
+    let mut entries = Vec::new();
+    for service in device.discover_services().await? {
+        let mut characteristics = Vec::new();
+        for characteristic in &service.characteristics {
+            let value = if characteristic.properties.contains(CharPropFlags::READ) {
+                match device.read(characteristic).await {
+                    Ok(value) => Some(to_hex(&value)),
+                    Err(e) => {
+                        eprintln!("❌ Could not read {}: {}", characteristic.uuid, e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            characteristics.push(CharacteristicEntry {
+                uuid: characteristic.uuid,
+                properties: characteristic
+                    .properties
+                    .iter_names()
+                    .map(|(name, _)| name.to_string())
+                    .collect(),
+                value,
+            });
+        }
+        entries.push(ServiceEntry {
+            uuid: service.uuid,
+            primary: service.primary,
+            characteristics,
+        });
+    }
+    Ok(entries)
+}
+
+async fn find_characteristic<D: Device>(device: &D, uuid: Uuid) -> Result<Characteristic> {
+    device
+        .discover_services()
+        .await?
+        .into_iter()
+        .flat_map(|service| service.characteristics)
+        .find(|characteristic| characteristic.uuid == uuid)
+        .ok_or_else(|| format!("{} has no characteristic {}", device.address(), uuid).into())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        BATTERY_LEVEL_UUID, BATTERY_SERVICE_UUID, BUTTON_SERVICE_UUID, BUTTON_STATE_UUID,
+        DEVICE_NAME,
+    };
+    use crate::fake::{FakeDevice, FakeScanner};
+
+    #[tokio::test(start_paused = true)]
+    async fn dumps_the_gatt_table_of_the_matching_device() {
+        let microbit = FakeDevice::new("AA:BB:CC:DD:EE:FF", DEVICE_NAME)
+            .with_service(
+                BUTTON_SERVICE_UUID,
+                &[(BUTTON_STATE_UUID, CharPropFlags::NOTIFY)],
+            )
+            .with_service(
+                BATTERY_SERVICE_UUID,
+                &[(
+                    BATTERY_LEVEL_UUID,
+                    CharPropFlags::READ | CharPropFlags::NOTIFY,
+                )],
+            )
+            .with_value(BATTERY_LEVEL_UUID, &[0x5c]);
+        let scanner = FakeScanner::new(vec![
+            FakeDevice::new("11:11:11:11:11:11", "Headphones"),
+            microbit,
+        ]);
+        let filter = DeviceFilter::new(None, vec!["aa:bb:cc:dd:ee:ff".to_string()], None);
+
+        let device = find_device(&scanner, &filter, Duration::from_secs(10))
+            .await
+            .unwrap();
+        let services = with_connection(&device, gatt_table(&device)).await.unwrap();
+
+        assert!(!device.is_connected().await.unwrap());
+        let battery = services
+            .iter()
+            .flat_map(|service| &service.characteristics)
+            .find(|characteristic| {
+                characteristic.uuid == Uuid::parse_str(BATTERY_LEVEL_UUID).unwrap()
+            })
+            .unwrap();
+        assert_eq!(battery.properties, ["READ", "NOTIFY"]);
+        assert_eq!(battery.value.as_deref(), Some("5c"));
+        assert_eq!(services.len(), 2);
+    }
+}
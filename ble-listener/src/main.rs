@@ -1,21 +1,36 @@
 mod bluetooth;
+mod buttons;
+mod cli;
+mod commands;
 mod config;
+mod decoders;
 mod event;
+#[cfg(test)]
+mod fake;
+mod inspect;
+mod sinks;
+mod spool;
+mod supervisor;
+mod transport;
+mod uplink;
 
-use btleplug::api::{Central, Manager as _, Peripheral as _};
-use btleplug::platform::Manager;
+use btleplug::api::{Central, Manager as _};
+use btleplug::platform::{Adapter, Manager};
+use clap::Parser;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
 use reqwest::Client;
 use std::error::Error;
+use tokio::sync::mpsc;
 
-use crate::bluetooth::{connect_and_listen, find_device};
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    println!("🚀 Starting BLE Button Tester with WebSocket");
-    println!("{}", "=".repeat(50));
-
-    let client = Client::new();
+use crate::cli::{Cli, Command};
+use crate::commands::CommandServer;
+use crate::sinks::{fan_out, spawn_sink, FileSink, HttpSink, MqttSink, SinkSpec, StdoutSink};
+use crate::spool::Spool;
+use crate::supervisor::supervise;
+use crate::uplink::{forward_events, Uplink};
 
+/// Picks the adapter called `name`, or the first one.
+async fn select_adapter(name: Option<&str>) -> Result<Adapter, Box<dyn Error>> {
     let manager = Manager::new()
         .await
         .map_err(|e| format!("Failed to create Bluetooth manager: {}", e))?;
@@ -25,35 +40,137 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .await
         .map_err(|e| format!("Failed to get Bluetooth adapters: {}", e))?;
 
-    let adapter = adapters
-        .into_iter()
-        .next()
-        .ok_or("No Bluetooth adapters found")?;
+    let Some(name) = name else {
+        return Ok(adapters
+            .into_iter()
+            .next()
+            .ok_or("No Bluetooth adapters found")?);
+    };
+    let mut available = Vec::new();
+    for adapter in adapters {
+        let info = adapter
+            .adapter_info()
+            .await
+            .map_err(|e| format!("Failed to get adapter info: {}", e))?;
+        // Adapter info starts with the name, e.g. "hci0 (usb:v1D6Bp0246d0537)".
+        if info.split_whitespace().next() == Some(name) {
+            return Ok(adapter);
+        }
+        available.push(info);
+    }
+    Err(format!(
+        "No Bluetooth adapter named {} (available: {})",
+        name,
+        available.join(", ")
+    )
+    .into())
+}
+
+async fn monitor(cli: Cli, adapter: Adapter) -> Result<(), Box<dyn Error>> {
+    eprintln!("🚀 Starting BLE Button Tester");
+    eprintln!("{}", "=".repeat(50));
+
+    let mut headers = HeaderMap::new();
+    if let Some(token) = &cli.server.token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|e| format!("Invalid token: {}", e))?;
+        headers.insert(AUTHORIZATION, value);
+    }
+    let client = Client::builder()
+        .default_headers(headers)
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    let mut senders = Vec::new();
+    let mut tasks = Vec::new();
+    let mut uses_server = false;
+    for spec in cli.sinks.sinks.clone() {
+        let (sender, task) = match &spec {
+            SinkSpec::Server if uses_server => continue,
+            SinkSpec::Server => {
+                uses_server = true;
+                let spool = Spool::open(&cli.spool.path, cli.spool.limits()).map_err(|e| {
+                    format!("Failed to open spool {}: {}", cli.spool.path.display(), e)
+                })?;
+                let uplink = Uplink {
+                    client: client.clone(),
+                    stream_url: cli.server.stream_url(),
+                    http_url: cli.server.events_url(),
+                    token: cli.server.token.clone(),
+                };
+                let (sender, received) = mpsc::unbounded_channel();
+                (
+                    sender,
+                    tokio::spawn(forward_events(uplink, spool, received)),
+                )
+            }
+            SinkSpec::Stdout => spawn_sink(spec.to_string(), StdoutSink),
+            SinkSpec::File(path) => {
+                let sink = FileSink::open(path, cli.sinks.file_max_bytes, cli.sinks.file_keep)
+                    .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+                spawn_sink(spec.to_string(), sink)
+            }
+            SinkSpec::Http(url) => spawn_sink(spec.to_string(), HttpSink::new(url.clone())),
+            SinkSpec::Mqtt(url) => {
+                let sink = MqttSink::connect(url)
+                    .map_err(|e| format!("Failed to set up MQTT sink {}: {}", url, e))?;
+                spawn_sink(spec.to_string(), sink)
+            }
+        };
+        eprintln!("📤 Sending events to {}", spec);
+        senders.push(sender);
+        tasks.push(task);
+    }
+
+    let (events, received) = mpsc::unbounded_channel();
+    let distributor = tokio::spawn(fan_out(received, senders));
 
     let adapter_info = adapter
         .adapter_info()
         .await
         .map_err(|e| format!("Failed to get adapter info: {}", e))?;
 
-    println!("Using adapter: {}", adapter_info);
+    eprintln!("Using adapter: {}", adapter_info);
 
-    match find_device(&adapter).await {
-        Ok(peripheral) => {
-            if let Err(e) = connect_and_listen(&peripheral, &client).await {
-                println!("❌ Connection error: {}", e);
-            }
+    // Commands are queued on ws-server, so only poll for them when it is one of the sinks.
+    let server = uses_server.then(|| CommandServer {
+        client,
+        url: cli.server.base_url(),
+    });
+    supervise(
+        adapter,
+        cli.devices.filter(),
+        cli.devices.scan_timeout(),
+        server,
+        events,
+    )
+    .await;
 
-            if peripheral.is_connected().await.unwrap_or(false) {
-                if let Err(e) = peripheral.disconnect().await {
-                    println!("❌ Failed to disconnect: {}", e);
-                }
-            }
-        }
-        Err(e) => {
-            println!("❌ Device discovery failed: {}", e);
-        }
+    // Let the sinks write what is still queued, including the final disconnects.
+    let _ = distributor.await;
+    for task in tasks {
+        let _ = task.await;
     }
 
-    println!("👋 Goodbye!");
+    eprintln!("👋 Goodbye!");
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut cli = Cli::parse();
+    let adapter = select_adapter(cli.devices.adapter.as_deref()).await?;
+
+    match cli.command.take().unwrap_or(Command::Monitor) {
+        Command::Monitor => monitor(cli, adapter).await,
+        command => Ok(inspect::run(
+            command,
+            &adapter,
+            &cli.devices.filter(),
+            cli.devices.scan_timeout(),
+            cli.format,
+        )
+        .await
+        .map_err(|e| e.to_string())?),
+    }
+}
@@ -0,0 +1,314 @@
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, QoS};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::config::{MQTT_DEFAULT_PORT, MQTT_DEFAULT_TOPIC, RECONNECT_INITIAL_DELAY_MS};
+use crate::event::ButtonEvent;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// A sink as given on the command line: `server`, `stdout`, `file=<path>`, `http=<url>`
+/// or `mqtt=<url>`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkSpec {
+    /// ws-server, over the acknowledged stream with the spool and HTTP fallback.
+    Server,
+    Stdout,
+    File(PathBuf),
+    Http(Url),
+    Mqtt(Url),
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, target) = match value.split_once('=') {
+            Some((kind, target)) => (kind, Some(target)),
+            None => (value, None),
+        };
+        let url = |target: Option<&str>| {
+            let target =
+                target.ok_or_else(|| format!("{} sink needs a URL: {}=<url>", kind, kind))?;
+            Url::parse(target).map_err(|e| format!("invalid {} sink URL {}: {}", kind, target, e))
+        };
+        match kind {
+            "server" => Ok(Self::Server),
+            "stdout" => Ok(Self::Stdout),
+            "file" => match target {
+                Some(path) if !path.is_empty() => Ok(Self::File(PathBuf::from(path))),
+                _ => Err("file sink needs a path: file=<path>".to_string()),
+            },
+            "http" => url(target).map(Self::Http),
+            "mqtt" => url(target).map(Self::Mqtt),
+            _ => Err(format!(
+                "unknown sink {:?}; expected server, stdout, file=<path>, http=<url> or mqtt=<url>",
+                kind
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SinkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Server => write!(f, "server"),
+            Self::Stdout => write!(f, "stdout"),
+            Self::File(path) => write!(f, "file={}", path.display()),
+            Self::Http(url) => write!(f, "http={}", url),
+            Self::Mqtt(url) => write!(f, "mqtt={}", url),
+        }
+    }
+}
+
+/// Somewhere events are written to, one at a time and in order.
+#[async_trait]
+pub trait Sink: Send + 'static {
+    async fn send(&mut self, event: &ButtonEvent) -> Result<()>;
+}
+
+/// Runs `sink` in its own task, so a slow or unreachable sink does not hold up the others.
+/// The task ends once the returned sender is dropped and the queue is drained.
+pub fn spawn_sink(
+    name: String,
+    mut sink: impl Sink,
+) -> (UnboundedSender<ButtonEvent>, JoinHandle<()>) {
+    let (events, mut received) = mpsc::unbounded_channel::<ButtonEvent>();
+    let task = tokio::spawn(async move {
+        while let Some(event) = received.recv().await {
+            if let Err(e) = sink.send(&event).await {
+                eprintln!(
+                    "❌ Sink {} failed to write {} {}: {}",
+                    name, event.button, event.state, e
+                );
+            }
+        }
+    });
+    (events, task)
+}
+
+/// Hands every event to each of `sinks`, in order.
+pub async fn fan_out(
+    mut events: UnboundedReceiver<ButtonEvent>,
+    sinks: Vec<UnboundedSender<ButtonEvent>>,
+) {
+    while let Some(event) = events.recv().await {
+        for sink in &sinks {
+            let _ = sink.send(event.clone());
+        }
+    }
+}
+
+/// Writes every event to stdout as one JSON line.
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn send(&mut self, event: &ButtonEvent) -> Result<()> {
+        let mut stdout = io::stdout().lock();
+        serde_json::to_writer(&mut stdout, event)?;
+        stdout.write_all(b"\n")?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Appends every event to a file as one JSON line. Once the file would grow past
+/// `max_bytes`, it is renamed to `<path>.1`, older files move up by one and the one past
+/// `keep` is deleted.
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl FileSink {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            written,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = rotated(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    async fn send(&mut self, event: &ButtonEvent) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// POSTs every event as JSON to a URL.
+pub struct HttpSink {
+    client: Client,
+    url: Url,
+}
+
+impl HttpSink {
+    /// The client carries no ingest token, so it is not leaked to other servers.
+    pub fn new(url: Url) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for HttpSink {
+    async fn send(&mut self, event: &ButtonEvent) -> Result<()> {
+        self.client
+            .post(self.url.clone())
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Publishes every event as JSON to an MQTT broker, given as
+/// `mqtt://[user:password@]host[:port][/topic]`, with QoS 1. Events go to
+/// `<topic>/<device>`.
+pub struct MqttSink {
+    client: AsyncClient,
+    topic: String,
+}
+
+impl MqttSink {
+    pub fn connect(url: &Url) -> Result<Self> {
+        if url.scheme() != "mqtt" {
+            return Err(format!("unsupported MQTT URL scheme {}", url.scheme()).into());
+        }
+        let host = url.host_str().ok_or("MQTT URL has no host")?;
+        let port = url.port().unwrap_or(MQTT_DEFAULT_PORT);
+        let topic = match url.path().trim_matches('/') {
+            "" => MQTT_DEFAULT_TOPIC.to_string(),
+            topic => topic.to_string(),
+        };
+
+        let mut options =
+            MqttOptions::new(format!("ble-listener-{}", std::process::id()), host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if !url.username().is_empty() {
+            options.set_credentials(url.username(), url.password().unwrap_or_default());
+        }
+        let (client, eventloop) = AsyncClient::new(options, 100);
+        tokio::spawn(drive(eventloop, format!("{}:{}", host, port)));
+        Ok(Self { client, topic })
+    }
+}
+
+/// Polls the MQTT connection, which is what sends queued messages and reconnects after
+/// errors.
+async fn drive(mut eventloop: EventLoop, broker: String) {
+    loop {
+        if let Err(e) = eventloop.poll().await {
+            eprintln!("❌ MQTT connection to {} failed: {}", broker, e);
+            time::sleep(Duration::from_millis(RECONNECT_INITIAL_DELAY_MS)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for MqttSink {
+    async fn send(&mut self, event: &ButtonEvent) -> Result<()> {
+        let topic = match &event.device {
+            Some(device) => format!("{}/{}", self.topic, device),
+            None => self.topic.clone(),
+        };
+        self.client
+            .publish(topic, QoS::AtLeastOnce, false, serde_json::to_vec(event)?)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rotates_the_file_once_it_is_full() {
+        let path = std::env::temp_dir().join(format!("ble-sink-{}.ndjson", std::process::id()));
+        let event = ButtonEvent {
+            button: "A".to_string(),
+            state: "PRESSED".to_string(),
+            timestamp: 1_728_000_000_000,
+            device: None,
+            battery: None,
+            duration_ms: None,
+            trace: None,
+        };
+        let line = serde_json::to_vec(&event).unwrap().len() as u64 + 1;
+
+        let mut sink = FileSink::open(&path, 2 * line, 2).unwrap();
+        for _ in 0..7 {
+            sink.send(&event).await.unwrap();
+        }
+        let lines = |path: &Path| fs::read_to_string(path).unwrap().lines().count();
+        let counts = [
+            lines(&path),
+            lines(&rotated(&path, 1)),
+            lines(&rotated(&path, 2)),
+        ];
+        let third = rotated(&path, 3).exists();
+        for file in [path.clone(), rotated(&path, 1), rotated(&path, 2)] {
+            let _ = fs::remove_file(file);
+        }
+
+        assert_eq!(counts, [1, 2, 2]);
+        assert!(!third);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::{now_millis, ButtonEvent};
+
+/// Which event gives way when the spool is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DropPolicy {
+    /// Discard the oldest undelivered event to make room.
+    Oldest,
+    /// Keep the spool as it is and discard the new event.
+    Newest,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "oldest" => Ok(Self::Oldest),
+            "newest" => Ok(Self::Newest),
+            _ => Err(format!("unknown drop policy {:?}", value)),
+        }
+    }
+}
+
+/// How much the spool retains.
+#[derive(Clone, Copy, Debug)]
+pub struct SpoolLimits {
+    pub max_events: usize,
+    /// Events older than this are discarded; `None` keeps them until delivered.
+    pub max_age: Option<Duration>,
+    pub drop_policy: DropPolicy,
+}
+
+/// An event waiting in the spool. `id` increases with every spooled event, including
+/// across restarts, and doubles as the sequence number on the event stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Spooled {
+    pub id: u64,
+    pub event: ButtonEvent,
+}
+
+/// Events that have not been delivered yet, kept in memory and in an append-only log of
+/// one JSON line per event. Delivered events are recorded in a cursor file next to the
+/// log (`<path>.cursor`) holding the last delivered id, and the log is compacted once
+/// at least half of it has been delivered. Reopening the spool restores the undelivered
+/// events in order.
+///
+/// Writes are small and synchronous. If one fails, the in-memory queue stays correct and
+/// only durability is lost, so callers just log the error.
+pub struct Spool {
+    path: PathBuf,
+    cursor_path: PathBuf,
+    log: File,
+    entries: VecDeque<Spooled>,
+    /// Lines at the start of the log that were already delivered or discarded.
+    stale_lines: usize,
+    next_id: u64,
+    limits: SpoolLimits,
+}
+
+impl Spool {
+    pub fn open(path: impl Into<PathBuf>, limits: SpoolLimits) -> io::Result<Self> {
+        let path = path.into();
+        let cursor_path = cursor_path(&path);
+        let delivered = match fs::read_to_string(&cursor_path) {
+            Ok(cursor) => cursor.trim().parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid spool cursor in {}", cursor_path.display()),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut entries = VecDeque::new();
+        let mut stale_lines = 0;
+        for line in contents.lines() {
+            match serde_json::from_str::<Spooled>(line) {
+                Ok(entry) if entry.id > delivered => entries.push_back(entry),
+                Ok(_) => stale_lines += 1,
+                Err(e) => {
+                    eprintln!("❌ Skipping unreadable spool entry: {}", e);
+                    stale_lines += 1;
+                }
+            }
+        }
+        let next_id = entries.back().map_or(delivered, |entry| entry.id) + 1;
+        let mut log = OpenOptions::new().create(true).append(true).open(&path)?;
+        // A line cut short by a crash must not swallow the next one.
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            log.write_all(b"\n")?;
+        }
+
+        let mut spool = Self {
+            path,
+            cursor_path,
+            log,
+            entries,
+            stale_lines,
+            next_id,
+            limits,
+        };
+        spool.expire()?;
+        spool.compact()?;
+        Ok(spool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn front(&self) -> Option<&Spooled> {
+        self.entries.front()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Spooled> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Spooled> {
+        self.entries.iter()
+    }
+
+    /// Appends `event`, first discarding expired events and, if the spool is full,
+    /// whichever event the drop policy picks.
+    pub fn push(&mut self, event: ButtonEvent) -> io::Result<()> {
+        let mut result = self.expire();
+        if self.entries.len() >= self.limits.max_events {
+            match self.limits.drop_policy {
+                DropPolicy::Oldest => {
+                    if let Some(oldest) = self.entries.front() {
+                        eprintln!(
+                            "🗑️ Spool full, dropping oldest event {} {}",
+                            oldest.event.button, oldest.event.state
+                        );
+                        let id = oldest.id;
+                        result = result.and(self.remove_through(id));
+                    }
+                }
+                DropPolicy::Newest => {
+                    eprintln!("🗑️ Spool full, dropping {} {}", event.button, event.state);
+                    return result;
+                }
+            }
+        }
+
+        let entry = Spooled {
+            id: self.next_id,
+            event,
+        };
+        self.next_id += 1;
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.entries.push_back(entry);
+        result
+            .and(self.log.write_all(line.as_bytes()))
+            .and(self.log.flush())
+    }
+
+    /// Removes every event up to and including `id`, once delivered or given up on.
+    pub fn remove_through(&mut self, id: u64) -> io::Result<()> {
+        let before = self.entries.len();
+        while self.entries.front().is_some_and(|entry| entry.id <= id) {
+            self.entries.pop_front();
+        }
+        if self.entries.len() == before {
+            return Ok(());
+        }
+        self.stale_lines += before - self.entries.len();
+
+        let tmp = self.cursor_path.with_extension("cursor.tmp");
+        fs::write(&tmp, id.to_string())?;
+        fs::rename(&tmp, &self.cursor_path)?;
+        self.compact()
+    }
+
+    fn expire(&mut self) -> io::Result<()> {
+        let Some(max_age) = self.limits.max_age else {
+            return Ok(());
+        };
+        let cutoff = now_millis().saturating_sub(max_age.as_millis() as u64);
+        let expired = self
+            .entries
+            .iter()
+            .take_while(|entry| entry.event.timestamp < cutoff)
+            .last()
+            .map(|entry| entry.id);
+        let Some(id) = expired else {
+            return Ok(());
+        };
+        let before = self.entries.len();
+        self.remove_through(id)?;
+        eprintln!(
+            "🗑️ Dropped {} expired events from the spool",
+            before - self.entries.len()
+        );
+        Ok(())
+    }
+
+    /// Rewrites the log without its stale lines once they make up at least half of it.
+    fn compact(&mut self) -> io::Result<()> {
+        if self.stale_lines == 0 || self.stale_lines < self.entries.len() {
+            return Ok(());
+        }
+        if self.entries.is_empty() {
+            self.log.set_len(0)?;
+        } else {
+            let tmp = self.path.with_extension("tmp");
+            let mut file = File::create(&tmp)?;
+            for entry in &self.entries {
+                serde_json::to_writer(&mut file, entry)?;
+                file.write_all(b"\n")?;
+            }
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)?;
+            self.log = OpenOptions::new().append(true).open(&self.path)?;
+        }
+        self.stale_lines = 0;
+        Ok(())
+    }
+}
+
+fn cursor_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".cursor");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(button: &str) -> ButtonEvent {
+        ButtonEvent {
+            button: button.to_string(),
+            state: "PRESSED".to_string(),
+            timestamp: now_millis(),
+            device: None,
+            battery: None,
+            duration_ms: None,
+            trace: None,
+        }
+    }
+
+    #[test]
+    fn keeps_undelivered_events_across_reopening() {
+        let path = std::env::temp_dir().join(format!("ble-spool-{}.ndjson", std::process::id()));
+        let limits = SpoolLimits {
+            max_events: 3,
+            max_age: Some(Duration::from_secs(60)),
+            drop_policy: DropPolicy::Oldest,
+        };
+
+        let mut spool = Spool::open(&path, limits).unwrap();
+        for button in ["A", "B", "A", "B"] {
+            spool.push(event(button)).unwrap();
+        }
+        spool.remove_through(2).unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(&path, limits).unwrap();
+        spool.push(event("A")).unwrap();
+        let ids: Vec<u64> = spool.iter().map(|entry| entry.id).collect();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(cursor_path(&path));
+
+        // Event 1 was dropped to make room for 4, and 2 was delivered.
+        assert_eq!(ids, [3, 4, 5]);
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::bluetooth::{connect_and_listen, discover_devices, DeviceFilter, Stopped};
+use crate::commands::CommandServer;
+use crate::config::{RECONNECT_INITIAL_DELAY_MS, RECONNECT_MAX_DELAY_MS};
+use crate::event::{connection_event, ButtonEvent};
+use crate::transport::{Device, Scanner};
+
+/// Exponential backoff with equal jitter: each delay is between half and all of the
+/// current step, and the step doubles up to `max`.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    step: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            step: initial,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self.step;
+        self.step = (step * 2).min(self.max);
+        let half = step / 2;
+        half + half.mul_f64(rand::random::<f64>())
+    }
+
+    pub fn reset(&mut self) {
+        self.step = self.initial;
+    }
+}
+
+/// Keeps scanning for devices matching `filter`, in rounds of `scan_timeout`, and starts a
+/// [`keep_connected`] task for each new one as soon as it is discovered, so every device
+/// gets its own connection and notification stream. Rounds that find no new device are
+/// followed by a backoff delay. Returns once Ctrl+C is pressed and every device task has
+/// stopped.
+pub async fn supervise<S: Scanner>(
+    scanner: S,
+    filter: DeviceFilter,
+    scan_timeout: Duration,
+    server: Option<CommandServer>,
+    events: UnboundedSender<ButtonEvent>,
+) {
+    let mut backoff = Backoff::new(
+        Duration::from_millis(RECONNECT_INITIAL_DELAY_MS),
+        Duration::from_millis(RECONNECT_MAX_DELAY_MS),
+    );
+    let mut tasks: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+    loop {
+        tasks.retain(|_, task| !task.is_finished());
+        let mut started = 0;
+        let scanned = tokio::select! {
+            scanned = discover_devices(&scanner, &filter, scan_timeout, |peripheral| {
+                tasks.entry(peripheral.address()).or_insert_with(|| {
+                    started += 1;
+                    tokio::spawn(keep_connected(
+                        scanner.clone(),
+                        peripheral,
+                        server.clone(),
+                        events.clone(),
+                    ))
+                });
+            }) => scanned,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+
+        if started > 0 {
+            backoff.reset();
+            continue;
+        }
+        match scanned {
+            Err(e) => eprintln!("❌ Device discovery failed: {}", e),
+            Ok(()) if tasks.is_empty() => eprintln!(
+                "❌ No device matching {} found. Make sure your micro:bit is running and advertising.",
+                filter
+            ),
+            Ok(()) => {}
+        }
+
+        let delay = backoff.next_delay();
+        eprintln!("🔁 Scanning again in {:.1}s...", delay.as_secs_f64());
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    // Every task sees the same Ctrl+C and stops after reporting its disconnect.
+    for (_, task) in tasks {
+        let _ = task.await;
+    }
+}
+
+/// Keeps one device connected: connects and listens, and after a failed attempt or a
+/// disconnect waits with backoff before connecting again. Connection changes are reported
+/// through `events`. Returns when Ctrl+C is pressed.
+async fn keep_connected<S: Scanner>(
+    scanner: S,
+    peripheral: S::Device,
+    server: Option<CommandServer>,
+    events: UnboundedSender<ButtonEvent>,
+) {
+    let device = peripheral.address();
+    let mut backoff = Backoff::new(
+        Duration::from_millis(RECONNECT_INITIAL_DELAY_MS),
+        Duration::from_millis(RECONNECT_MAX_DELAY_MS),
+    );
+
+    loop {
+        let result = match scanner.disconnections().await {
+            Ok(disconnections) => {
+                connect_and_listen(&peripheral, server.as_ref(), &events, disconnections).await
+            }
+            Err(e) => Err(format!("Failed to watch for disconnections: {}", e).into()),
+        };
+
+        // `CONNECTED` was sent exactly when listening started.
+        if result.is_ok() {
+            let _ = events.send(connection_event(&device, "DISCONNECTED"));
+        }
+        if peripheral.is_connected().await.unwrap_or(false) {
+            if let Err(e) = peripheral.disconnect().await {
+                eprintln!("❌ Failed to disconnect from {}: {}", device, e);
+            }
+        }
+
+        match result {
+            Ok(Stopped::Interrupted) => return,
+            Ok(Stopped::Disconnected) => backoff.reset(),
+            Err(e) => eprintln!("❌ Connection error on {}: {}", device, e),
+        }
+
+        let delay = backoff.next_delay();
+        eprintln!(
+            "🔁 Reconnecting to {} in {:.1}s...",
+            device,
+            delay.as_secs_f64()
+        );
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = tokio::signal::ctrl_c() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BUTTON_SERVICE_UUID, BUTTON_STATE_UUID, DEVICE_NAME};
+    use crate::fake::{FakeDevice, FakeScanner};
+    use btleplug::api::CharPropFlags;
+    use tokio::sync::mpsc;
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_every_matching_device_connected() {
+        let device = |address: &str, name: &str| {
+            FakeDevice::new(address, name)
+                .advertising(&[BUTTON_SERVICE_UUID])
+                .with_service(
+                    BUTTON_SERVICE_UUID,
+                    &[(BUTTON_STATE_UUID, CharPropFlags::NOTIFY)],
+                )
+                .notify(BUTTON_STATE_UUID, &[1])
+        };
+        let scanner = FakeScanner::new(vec![
+            device("AA:AA:AA:AA:AA:AA", &format!("{} [zogav]", DEVICE_NAME)),
+            device("BB:BB:BB:BB:BB:BB", "BBC micro:bit"),
+            device("CC:CC:CC:CC:CC:CC", "Headphones"),
+        ]);
+        let filter = DeviceFilter {
+            name_prefix: Some(DEVICE_NAME.to_string()),
+            addresses: vec!["bb:bb:bb:bb:bb:bb".to_string()],
+            service: None,
+        };
+
+        let (events, mut received) = mpsc::unbounded_channel::<ButtonEvent>();
+        let collect = async {
+            let mut states: HashMap<String, Vec<String>> = HashMap::new();
+            while states.values().filter(|states| states.len() >= 6).count() < 2 {
+                let event = received.recv().await.unwrap();
+                states
+                    .entry(event.device.unwrap())
+                    .or_default()
+                    .push(format!("{} {}", event.button, event.state));
+            }
+            states
+        };
+        let states = tokio::select! {
+            states = collect => states,
+            _ = supervise(scanner, filter, Duration::from_secs(10), None, events) => {
+                panic!("supervisor stopped")
+            }
+        };
+
+        assert!(!states.contains_key("CC:CC:CC:CC:CC:CC"));
+        for device in ["AA:AA:AA:AA:AA:AA", "BB:BB:BB:BB:BB:BB"] {
+            assert_eq!(
+                states[device][..6],
+                [
+                    "CONNECTION CONNECTED",
+                    "A PRESSED",
+                    "CONNECTION DISCONNECTED",
+                    "CONNECTION CONNECTED",
+                    "A PRESSED",
+                    "CONNECTION DISCONNECTED",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_within_jitter_bounds_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(800));
+        let check = |backoff: &mut Backoff, steps: &[u64]| {
+            for &step in steps {
+                let delay = backoff.next_delay();
+                let step = Duration::from_millis(step);
+                assert!(
+                    delay >= step / 2 && delay <= step,
+                    "{:?} for {:?}",
+                    delay,
+                    step
+                );
+            }
+        };
+        check(&mut backoff, &[100, 200, 400, 800, 800, 800]);
+
+        backoff.reset();
+        check(&mut backoff, &[100, 200]);
+    }
+}
@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use btleplug::api::{
+    Central, CentralEvent, Characteristic, Peripheral as _, ScanFilter, Service, ValueNotification,
+    WriteType,
+};
+use btleplug::platform::{Adapter, Peripheral};
+use btleplug::Result;
+use futures::stream::{Stream, StreamExt};
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use uuid::Uuid;
+
+pub type Notifications = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+pub type Disconnections = Pin<Box<dyn Stream<Item = String> + Send>>;
+pub type Discoveries<D> = Pin<Box<dyn Stream<Item = D> + Send>>;
+
+/// What a peripheral advertises while scanning.
+#[derive(Clone, Debug, Default)]
+pub struct Advertisement {
+    pub name: Option<String>,
+    pub services: Vec<Uuid>,
+    /// Signal strength of the latest advertisement, in dBm.
+    pub rssi: Option<i16>,
+}
+
+/// The part of a Bluetooth adapter the listener uses to find devices.
+#[async_trait]
+pub trait Scanner: Clone + Send + Sync + 'static {
+    type Device: Device;
+
+    /// Scans for devices advertising any of `services`, or for every device if empty.
+    async fn start_scan(&self, services: Vec<Uuid>) -> Result<()>;
+
+    async fn stop_scan(&self) -> Result<()>;
+
+    /// Devices already known to the adapter, followed by new ones as they are discovered.
+    async fn discoveries(&self) -> Result<Discoveries<Self::Device>>;
+
+    /// Addresses of devices as they disconnect.
+    async fn disconnections(&self) -> Result<Disconnections>;
+}
+
+/// The part of a BLE peripheral the listener uses once it has been found.
+#[async_trait]
+pub trait Device: Clone + Send + Sync + 'static {
+    fn address(&self) -> String;
+
+    async fn advertisement(&self) -> Result<Advertisement>;
+
+    async fn connect(&self) -> Result<()>;
+
+    async fn is_connected(&self) -> Result<bool>;
+
+    async fn disconnect(&self) -> Result<()>;
+
+    /// Discovers the GATT table and returns its services.
+    async fn discover_services(&self) -> Result<BTreeSet<Service>>;
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()>;
+
+    /// Notifications from every subscribed characteristic; the stream ends on disconnect.
+    async fn notifications(&self) -> Result<Notifications>;
+}
+
+#[async_trait]
+impl Scanner for Adapter {
+    type Device = Peripheral;
+
+    async fn start_scan(&self, services: Vec<Uuid>) -> Result<()> {
+        Central::start_scan(self, ScanFilter { services }).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        Central::stop_scan(self).await
+    }
+
+    async fn discoveries(&self) -> Result<Discoveries<Peripheral>> {
+        // Subscribe before listing, so a device found in between is not missed.
+        let adapter = self.clone();
+        let events = self.events().await?;
+        let known = self.peripherals().await?;
+        let discovered = events.filter_map(move |event| {
+            let adapter = adapter.clone();
+            async move {
+                match event {
+                    CentralEvent::DeviceDiscovered(id) => adapter.peripheral(&id).await.ok(),
+                    _ => None,
+                }
+            }
+        });
+        Ok(Box::pin(futures::stream::iter(known).chain(discovered)))
+    }
+
+    async fn disconnections(&self) -> Result<Disconnections> {
+        let adapter = self.clone();
+        let events = self.events().await?;
+        Ok(Box::pin(
+            events
+                .filter_map(move |event| {
+                    let adapter = adapter.clone();
+                    async move {
+                        match event {
+                            CentralEvent::DeviceDisconnected(id) => {
+                                let peripheral = adapter.peripheral(&id).await.ok()?;
+                                Some(btleplug::api::Peripheral::address(&peripheral).to_string())
+                            }
+                            _ => None,
+                        }
+                    }
+                })
+                .fuse(),
+        ))
+    }
+}
+
+#[async_trait]
+impl Device for Peripheral {
+    fn address(&self) -> String {
+        btleplug::api::Peripheral::address(self).to_string()
+    }
+
+    async fn advertisement(&self) -> Result<Advertisement> {
+        Ok(self
+            .properties()
+            .await?
+            .map(|p| Advertisement {
+                name: p.local_name,
+                services: p.services,
+                rssi: p.rssi,
+            })
+            .unwrap_or_default())
+    }
+
+    async fn connect(&self) -> Result<()> {
+        btleplug::api::Peripheral::connect(self).await
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        btleplug::api::Peripheral::is_connected(self).await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        btleplug::api::Peripheral::disconnect(self).await
+    }
+
+    async fn discover_services(&self) -> Result<BTreeSet<Service>> {
+        btleplug::api::Peripheral::discover_services(self).await?;
+        Ok(self.services())
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        btleplug::api::Peripheral::subscribe(self, characteristic).await
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        btleplug::api::Peripheral::read(self, characteristic).await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        btleplug::api::Peripheral::write(self, characteristic, data, write_type).await
+    }
+
+    async fn notifications(&self) -> Result<Notifications> {
+        btleplug::api::Peripheral::notifications(self).await
+    }
+}
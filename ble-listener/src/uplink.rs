@@ -0,0 +1,418 @@
+use futures::stream::{SplitSink, StreamExt};
+use futures::SinkExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::{self, Instant};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{header::AUTHORIZATION, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::config::{ACK_TIMEOUT_MS, RECONNECT_INITIAL_DELAY_MS, RECONNECT_MAX_DELAY_MS};
+use crate::event::{now_micros, send_button_event, ButtonEvent};
+use crate::spool::{Spool, Spooled};
+use crate::supervisor::Backoff;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Where events are delivered: a WebSocket stream, with HTTP POST as the fallback.
+#[derive(Clone)]
+pub struct Uplink {
+    pub client: Client,
+    pub stream_url: String,
+    pub http_url: String,
+    /// Sent as a bearer token when opening the stream; `client` carries it for HTTP.
+    pub token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Frame<'a> {
+    seq: u64,
+    event: &'a ButtonEvent,
+}
+
+#[derive(Deserialize)]
+struct Ack {
+    seq: u64,
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+enum LinkEnd {
+    /// The event channel closed and every event was acknowledged.
+    Drained,
+    /// The server is rate limiting the events and asked for them to be sent again later.
+    Throttled,
+    Lost(String),
+}
+
+/// Delivers events to ws-server in the order they were received. Every event is written
+/// to `spool` first and only removed once delivered, so nothing is lost while the server
+/// is unreachable or the listener restarts; events left from a previous run go first.
+///
+/// Events go over one persistent WebSocket, using their spool id as sequence number, and
+/// are delivered when the server acknowledges them. While the stream is down they are
+/// posted over HTTP instead, stopping at the first one the server does not take, and the
+/// stream is reopened with backoff. An event the server rate limits stays in the spool
+/// and the stream is reopened after a separate backoff, without falling back to HTTP,
+/// whose requests would be limited as well. Returns once the channel is closed and the spool is
+/// drained, or, if the server cannot be reached, left for the next start.
+pub async fn forward_events(
+    uplink: Uplink,
+    mut spool: Spool,
+    mut events: UnboundedReceiver<ButtonEvent>,
+) {
+    if !spool.is_empty() {
+        eprintln!("💾 Resuming with {} spooled events", spool.len());
+    }
+    let mut closed = false;
+    let mut backoff = Backoff::new(
+        Duration::from_millis(RECONNECT_INITIAL_DELAY_MS),
+        Duration::from_millis(RECONNECT_MAX_DELAY_MS),
+    );
+    let mut throttle = Backoff::new(
+        Duration::from_millis(RECONNECT_INITIAL_DELAY_MS),
+        Duration::from_millis(RECONNECT_MAX_DELAY_MS),
+    );
+
+    loop {
+        let mut throttled = false;
+        match connect(&uplink).await {
+            Ok(socket) => {
+                eprintln!("🔗 Streaming events to {}", uplink.stream_url);
+                backoff.reset();
+                match stream_events(socket, &mut spool, &mut events, &mut closed).await {
+                    LinkEnd::Drained => return,
+                    LinkEnd::Throttled => {
+                        eprintln!("⏳ Web server is rate limiting events; retrying later");
+                        throttled = true;
+                    }
+                    LinkEnd::Lost(reason) => {
+                        eprintln!("❌ Event stream lost: {}; falling back to HTTP", reason)
+                    }
+                }
+            }
+            Err(e) => eprintln!("❌ Failed to open event stream: {}; using HTTP", e),
+        }
+
+        let (mut reachable, delay) = if throttled {
+            // The server is up, so keep retrying it even once the channel is closed.
+            (false, throttle.next_delay())
+        } else {
+            throttle.reset();
+            let reachable = drain_over_http(&uplink, &mut spool).await;
+            if closed {
+                if !spool.is_empty() {
+                    eprintln!(
+                        "💾 {} events kept in the spool for the next start",
+                        spool.len()
+                    );
+                }
+                return;
+            }
+            (reachable, backoff.next_delay())
+        };
+
+        let retry = time::sleep(delay);
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                event = events.recv(), if !closed => match event {
+                    Some(event) => {
+                        if let Err(e) = spool.push(event) {
+                            eprintln!("❌ Failed to write event to the spool: {}", e);
+                        }
+                        // After a failure, wait for the retry instead of trying every event.
+                        if reachable {
+                            reachable = drain_over_http(&uplink, &mut spool).await;
+                        }
+                    }
+                    None => {
+                        closed = true;
+                        break;
+                    }
+                },
+                _ = &mut retry => break,
+            }
+        }
+    }
+}
+
+/// Posts spooled events in order until the spool is empty, returning `false` if one of
+/// them has to wait for a later attempt.
+async fn drain_over_http(uplink: &Uplink, spool: &mut Spool) -> bool {
+    while let Some(entry) = spool.front() {
+        let id = entry.id;
+        if !send_button_event(&uplink.client, &uplink.http_url, entry.event.clone()).await {
+            return false;
+        }
+        if let Err(e) = spool.remove_through(id) {
+            eprintln!("❌ Failed to update the spool: {}", e);
+        }
+    }
+    true
+}
+
+async fn connect(uplink: &Uplink) -> Result<Socket, Box<dyn Error + Send + Sync>> {
+    let mut request = uplink.stream_url.as_str().into_client_request()?;
+    if let Some(token) = &uplink.token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+        request.headers_mut().insert(AUTHORIZATION, value);
+    }
+    let (socket, _) = tokio_tungstenite::connect_async(request).await?;
+    Ok(socket)
+}
+
+async fn send_frame(sink: &mut SplitSink<Socket, Message>, entry: &Spooled) -> Result<(), String> {
+    let mut event = entry.event.clone();
+    if let Some(trace) = event.trace.as_mut() {
+        trace.listener_sent_at = Some(now_micros());
+    }
+    let frame = Frame {
+        seq: entry.id,
+        event: &event,
+    };
+    let json = serde_json::to_string(&frame).map_err(|e| e.to_string())?;
+    sink.send(Message::text(json))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn stream_events(
+    socket: Socket,
+    spool: &mut Spool,
+    events: &mut UnboundedReceiver<ButtonEvent>,
+    closed: &mut bool,
+) -> LinkEnd {
+    let ack_timeout = Duration::from_millis(ACK_TIMEOUT_MS);
+    let (mut sink, mut acks) = socket.split();
+    // Spool ids sent on this link and still waiting for an ack, with when they were sent.
+    let mut in_flight: VecDeque<(u64, Instant)> = VecDeque::new();
+    let mut sent_through = 0;
+
+    loop {
+        let unsent = sent_through;
+        for entry in spool.iter().filter(|entry| entry.id > unsent) {
+            if let Err(e) = send_frame(&mut sink, entry).await {
+                return LinkEnd::Lost(e);
+            }
+            in_flight.push_back((entry.id, Instant::now()));
+            sent_through = entry.id;
+        }
+        // Events the spool dropped no longer need an ack.
+        let first = spool.front().map_or(u64::MAX, |entry| entry.id);
+        while in_flight.front().is_some_and(|&(id, _)| id < first) {
+            in_flight.pop_front();
+        }
+
+        if *closed && spool.is_empty() {
+            let _ = sink.close().await;
+            return LinkEnd::Drained;
+        }
+        let oldest = in_flight.front().map(|&(_, sent_at)| sent_at);
+
+        tokio::select! {
+            event = events.recv(), if !*closed => match event {
+                Some(event) => {
+                    if let Err(e) = spool.push(event) {
+                        eprintln!("❌ Failed to write event to the spool: {}", e);
+                    }
+                }
+                None => *closed = true,
+            },
+            message = acks.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if handle_ack(&text, spool) {
+                        // Later acks would settle the event, so stop reading them.
+                        let _ = sink.close().await;
+                        return LinkEnd::Throttled;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    return LinkEnd::Lost("closed by the server".to_string());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return LinkEnd::Lost(e.to_string()),
+            },
+            _ = time::sleep_until(oldest.unwrap_or_else(Instant::now) + ack_timeout),
+                if oldest.is_some() =>
+            {
+                return LinkEnd::Lost(format!(
+                    "no acknowledgement within {} ms",
+                    ACK_TIMEOUT_MS
+                ));
+            }
+        }
+    }
+}
+
+/// Acks arrive in order, so one for `seq` settles every event up to it, unless the
+/// server asks for the event to be retried. Returns whether it did.
+fn handle_ack(text: &str, spool: &mut Spool) -> bool {
+    let ack: Ack = match serde_json::from_str(text) {
+        Ok(ack) => ack,
+        Err(e) => {
+            eprintln!("❌ Invalid acknowledgement from web server: {}", e);
+            return false;
+        }
+    };
+    if ack.status == "retry" {
+        return true;
+    }
+    if let Some(Spooled { event, .. }) = spool.get(ack.seq) {
+        if ack.status == "accepted" {
+            eprintln!("📤 Sent {} {} to web server", event.button, event.state);
+        } else {
+            eprintln!(
+                "❌ Web server rejected {} {}: {}",
+                event.button,
+                event.state,
+                ack.error.as_deref().unwrap_or("no reason given")
+            );
+        }
+    }
+    if let Err(e) = spool.remove_through(ack.seq) {
+        eprintln!("❌ Failed to update the spool: {}", e);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Trace;
+    use crate::spool::{DropPolicy, SpoolLimits};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn event(button: &str) -> ButtonEvent {
+        ButtonEvent {
+            button: button.to_string(),
+            state: "PRESSED".to_string(),
+            timestamp: 0,
+            device: Some("AA:BB:CC:DD:EE:FF".to_string()),
+            battery: None,
+            duration_ms: None,
+            trace: Some(Trace::default()),
+        }
+    }
+
+    fn test_uplink(address: std::net::SocketAddr) -> Uplink {
+        Uplink {
+            client: Client::new(),
+            stream_url: format!("ws://{}/api/button/stream", address),
+            // Nothing listens here, so an event that fell back to HTTP would be lost.
+            http_url: "http://127.0.0.1:9/api/button".to_string(),
+            token: None,
+        }
+    }
+
+    fn test_spool(name: &str) -> (std::path::PathBuf, SpoolLimits) {
+        let path =
+            std::env::temp_dir().join(format!("ble-uplink-{}-{}.ndjson", name, std::process::id()));
+        let limits = SpoolLimits {
+            max_events: 10,
+            max_age: None,
+            drop_policy: DropPolicy::Oldest,
+        };
+        (path, limits)
+    }
+
+    fn remove_spool(path: &std::path::Path) {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(path.with_extension("ndjson.cursor"));
+    }
+
+    #[tokio::test]
+    async fn streams_events_in_order_until_acknowledged() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut received = Vec::new();
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                let frame: serde_json::Value = serde_json::from_str(&text).unwrap();
+                let seq = frame["seq"].as_u64().unwrap();
+                received.push((seq, frame["event"]["button"].as_str().unwrap().to_string()));
+                let ack = format!(r#"{{"seq":{},"status":"accepted"}}"#, seq);
+                socket.send(Message::text(ack)).await.unwrap();
+            }
+            received
+        });
+
+        let uplink = test_uplink(address);
+        let (path, limits) = test_spool("ordered");
+        let spool = Spool::open(&path, limits).unwrap();
+        let (events, received) = mpsc::unbounded_channel();
+        for button in ["A", "B", "A"] {
+            events.send(event(button)).unwrap();
+        }
+        drop(events);
+        forward_events(uplink, spool, received).await;
+        assert!(Spool::open(&path, limits).unwrap().is_empty());
+        remove_spool(&path);
+
+        let received = server.await.unwrap();
+        assert_eq!(
+            received,
+            [
+                (1, "A".to_string()),
+                (2, "B".to_string()),
+                (3, "A".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limited_events_are_kept_and_sent_again() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            // The first connection is over its rate limit and asks for a retry.
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            if let Some(Ok(Message::Text(text))) = socket.next().await {
+                let frame: serde_json::Value = serde_json::from_str(&text).unwrap();
+                let ack = format!(
+                    r#"{{"seq":{},"status":"retry","error":"Event rate limit exceeded"}}"#,
+                    frame["seq"]
+                );
+                socket.send(Message::text(ack)).await.unwrap();
+            }
+            while let Some(Ok(_)) = socket.next().await {}
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let mut received = Vec::new();
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                let frame: serde_json::Value = serde_json::from_str(&text).unwrap();
+                let seq = frame["seq"].as_u64().unwrap();
+                received.push((seq, frame["event"]["button"].as_str().unwrap().to_string()));
+                let ack = format!(r#"{{"seq":{},"status":"accepted"}}"#, seq);
+                socket.send(Message::text(ack)).await.unwrap();
+            }
+            received
+        });
+
+        let (path, limits) = test_spool("throttled");
+        let spool = Spool::open(&path, limits).unwrap();
+        let (events, received) = mpsc::unbounded_channel();
+        for button in ["A", "B"] {
+            events.send(event(button)).unwrap();
+        }
+        drop(events);
+        forward_events(test_uplink(address), spool, received).await;
+        assert!(Spool::open(&path, limits).unwrap().is_empty());
+        remove_spool(&path);
+
+        let received = server.await.unwrap();
+        assert_eq!(received, [(1, "A".to_string()), (2, "B".to_string())]);
+    }
+}
@@ -3,13 +3,20 @@
 
 use {defmt_rtt as _, panic_probe as _};
 
+use core::sync::atomic::{AtomicU32, Ordering};
 use defmt::{info, unwrap, warn};
 use embassy_executor::Spawner;
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select, select3, Either, Either3};
 use embassy_sync::{
     blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
 };
-use microbit_bsp::{ble::MultiprotocolServiceLayer, Config, Microbit};
+use heapless::{String, Vec};
+use microbit_bsp::{
+    ble::MultiprotocolServiceLayer,
+    embassy_nrf::{peripherals::PWM0, pwm::SimplePwm},
+    speaker::{Note, Pitch, PwmSpeaker},
+    Config, LedMatrix, Microbit,
+};
 use trouble_host::prelude::*;
 
 /// Max number of connections
@@ -33,6 +40,59 @@ static BUTTON_CHANNEL: Channel<CriticalSectionRawMutex, ButtonEvent, 10> = Chann
 // Connection state signal
 static CONNECTION_STATE: Signal<CriticalSectionRawMutex, bool> = Signal::new();
 
+/// Longest text a show-text command carries: a 20-byte write at the default ATT MTU,
+/// less the opcode.
+const TEXT_MAX: usize = 19;
+
+/// Commands written to the command characteristic, as one opcode byte followed by the
+/// little-endian arguments.
+pub enum DeviceCommand {
+    /// `0x01` + UTF-8 text: scroll the text across the LED matrix.
+    ShowText(String<TEXT_MAX>),
+    /// `0x02` + frequency in Hz (u16) + duration in ms (u16): play a tone.
+    PlayTone { frequency: u16, duration_ms: u16 },
+    /// `0x03`: reset the button press counters.
+    ResetCounters,
+}
+
+impl DeviceCommand {
+    fn decode(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x01, text @ ..] if !text.is_empty() => core::str::from_utf8(text)
+                .ok()
+                .and_then(|text| String::try_from(text).ok())
+                .map(Self::ShowText),
+            [0x02, f0, f1, d0, d1] => {
+                let frequency = u16::from_le_bytes([*f0, *f1]);
+                (frequency > 0).then_some(Self::PlayTone {
+                    frequency,
+                    duration_ms: u16::from_le_bytes([*d0, *d1]),
+                })
+            }
+            [0x03] => Some(Self::ResetCounters),
+            _ => None,
+        }
+    }
+
+    fn opcode(&self) -> u8 {
+        match self {
+            Self::ShowText(_) => 0x01,
+            Self::PlayTone { .. } => 0x02,
+            Self::ResetCounters => 0x03,
+        }
+    }
+}
+
+// Commands accepted over BLE, run one at a time by the command task
+static COMMAND_CHANNEL: Channel<CriticalSectionRawMutex, DeviceCommand, 4> = Channel::new();
+
+// Opcodes of the commands that have run, to be confirmed to the central in order
+static CONFIRM_CHANNEL: Channel<CriticalSectionRawMutex, u8, 4> = Channel::new();
+
+// Button presses since boot or the last reset-counters command
+static PRESSES_A: AtomicU32 = AtomicU32::new(0);
+static PRESSES_B: AtomicU32 = AtomicU32::new(0);
+
 // GATT Server definition
 #[gatt_server]
 struct Server {
@@ -49,24 +109,54 @@ struct BatteryService {
     level: u8,
 }
 
+/// 128-bit UUIDs of the button service. They are written in the usual reading order and
+/// stored little-endian, the byte order trouble-host sends them in, so a central sees the
+/// same UUIDs in the GATT table and in the advertisement.
 pub mod button_uuids {
+    const fn le(uuid: [u8; 16]) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        let mut i = 0;
+        while i < 16 {
+            bytes[i] = uuid[15 - i];
+            i += 1;
+        }
+        bytes
+    }
+
     // EF680800-9B35-4933-9B10-52FFA9740042
-    pub const SERVICE: [u8; 16] = [
+    pub const SERVICE: [u8; 16] = le([
         0xEF, 0x68, 0x08, 0x00, 0x9B, 0x35, 0x49, 0x33, 0x9B, 0x10, 0x52, 0xFF, 0xA9, 0x74, 0x00,
         0x42,
-    ];
+    ]);
 
     // EF680801-9B35-4933-9B10-52FFA9740042
-    pub const BUTTON_STATE: [u8; 16] = [
+    pub const BUTTON_STATE: [u8; 16] = le([
         0xEF, 0x68, 0x08, 0x01, 0x9B, 0x35, 0x49, 0x33, 0x9B, 0x10, 0x52, 0xFF, 0xA9, 0x74, 0x00,
         0x42,
-    ];
+    ]);
+
+    // EF680802-9B35-4933-9B10-52FFA9740042
+    pub const COMMAND: [u8; 16] = le([
+        0xEF, 0x68, 0x08, 0x02, 0x9B, 0x35, 0x49, 0x33, 0x9B, 0x10, 0x52, 0xFF, 0xA9, 0x74, 0x00,
+        0x42,
+    ]);
 }
 
 #[gatt_service(uuid = button_uuids::SERVICE)]
 struct ButtonService {
     #[characteristic(uuid = button_uuids::BUTTON_STATE, notify)]
-    button_state: u8,
+    button_state: [u8; 5],
+    /// Written with a [`DeviceCommand`]; once the command has run, the device notifies its
+    /// opcode followed by a `0` status byte.
+    #[characteristic(uuid = button_uuids::COMMAND, write, notify)]
+    command: Vec<u8, 20>,
+}
+
+/// Button state notification: the state byte followed by the uptime in milliseconds
+/// (little-endian u32), which the listener uses for latency tracing.
+fn button_payload(state: u8) -> [u8; 5] {
+    let tick = (embassy_time::Instant::now().as_millis() as u32).to_le_bytes();
+    [state, tick[0], tick[1], tick[2], tick[3]]
 }
 
 #[embassy_executor::task]
@@ -82,7 +172,8 @@ async fn button_task(mut btn_a: microbit_bsp::Button, mut btn_b: microbit_bsp::B
         match select(btn_a.wait_for_low(), btn_b.wait_for_low()).await {
             Either::First(()) => {
                 // Button A pressed
-                info!("[button] Button A (LEFT) pressed!");
+                let presses = PRESSES_A.fetch_add(1, Ordering::Relaxed) + 1;
+                info!("[button] Button A (LEFT) pressed! ({} presses)", presses);
                 sender.send(ButtonEvent::APressed).await;
                 btn_a.wait_for_high().await;
                 info!("[button] Button A released");
@@ -90,7 +181,8 @@ async fn button_task(mut btn_a: microbit_bsp::Button, mut btn_b: microbit_bsp::B
             }
             Either::Second(()) => {
                 // Button B pressed
-                info!("[button] Button B (RIGHT) pressed!");
+                let presses = PRESSES_B.fetch_add(1, Ordering::Relaxed) + 1;
+                info!("[button] Button B (RIGHT) pressed! ({} presses)", presses);
                 sender.send(ButtonEvent::BPressed).await;
                 btn_b.wait_for_high().await;
                 info!("[button] Button B released");
@@ -100,6 +192,36 @@ async fn button_task(mut btn_a: microbit_bsp::Button, mut btn_b: microbit_bsp::B
     }
 }
 
+#[embassy_executor::task]
+async fn command_task(mut display: LedMatrix, mut speaker: PwmSpeaker<'static, PWM0>) {
+    let receiver = COMMAND_CHANNEL.receiver();
+    let confirmations = CONFIRM_CHANNEL.sender();
+
+    loop {
+        let command = receiver.receive().await;
+        match &command {
+            DeviceCommand::ShowText(text) => {
+                info!("[command] Showing {}", text.as_str());
+                display.scroll(text.as_str()).await;
+            }
+            DeviceCommand::PlayTone {
+                frequency,
+                duration_ms,
+            } => {
+                info!("[command] Playing {} Hz for {} ms", frequency, duration_ms);
+                let note = Note(Pitch::Frequency(*frequency as u32), *duration_ms as u32);
+                speaker.play(&note).await;
+            }
+            DeviceCommand::ResetCounters => {
+                info!("[command] Resetting counters");
+                PRESSES_A.store(0, Ordering::Relaxed);
+                PRESSES_B.store(0, Ordering::Relaxed);
+            }
+        }
+        confirmations.send(command.opcode()).await;
+    }
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let board = Microbit::new(Config::default());
@@ -110,6 +232,8 @@ async fn main(spawner: Spawner) {
 
     // Spawn button task
     spawner.must_spawn(button_task(board.btn_a, board.btn_b));
+    let speaker = PwmSpeaker::new(SimplePwm::new_1ch(board.pwm0, board.speaker));
+    spawner.must_spawn(command_task(board.display, speaker));
     spawner.must_spawn(mpsl_task(mpsl));
 
     run(sdc).await;
@@ -171,22 +295,29 @@ async fn advertise<'a, 'b, C: Controller>(
     peripheral: &mut Peripheral<'a, C, DefaultPacketPool>,
     server: &'b Server<'_>,
 ) -> Result<GattConnection<'a, 'b, DefaultPacketPool>, BleHostError<C::Error>> {
+    // The button service is what centrals filter their scans on. With the flags and the
+    // name it fills the 31-byte advertising data, so the 16-bit UUIDs go in the scan response.
     let mut advertiser_data = [0; 31];
-    AdStructure::encode_slice(
+    let len = AdStructure::encode_slice(
         &[
             AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-            AdStructure::ServiceUuids16(&[[0x0f, 0x18], [0x00, 0x08]]),
+            AdStructure::ServiceUuids128(&[button_uuids::SERVICE]),
             AdStructure::CompleteLocalName(name.as_bytes()),
         ],
         &mut advertiser_data[..],
     )?;
+    let mut scan_data = [0; 31];
+    let scan_len = AdStructure::encode_slice(
+        &[AdStructure::ServiceUuids16(&[[0x0f, 0x18], [0x00, 0x08]])],
+        &mut scan_data[..],
+    )?;
 
     let advertiser = peripheral
         .advertise(
             &Default::default(),
             Advertisement::ConnectableScannableUndirected {
-                adv_data: &advertiser_data[..],
-                scan_data: &[],
+                adv_data: &advertiser_data[..len],
+                scan_data: &scan_data[..scan_len],
             },
         )
         .await?;
@@ -200,20 +331,24 @@ async fn advertise<'a, 'b, C: Controller>(
 async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>) {
     let level = server.battery_service.level;
     let button_state = server.button_service.button_state;
+    let command = server.button_service.command;
 
     info!("Setting initial battery level to 100");
     unwrap!(level.set(server, &100));
     info!("Setting initial button state to 0");
-    unwrap!(button_state.set(server, &0)); // Initial state: no button pressed
+    unwrap!(button_state.set(server, &button_payload(0))); // Initial state: no button pressed
 
     info!("Connection established. Press buttons A or B to send events!");
     info!("Waiting for GATT events or button events...");
 
     let receiver = BUTTON_CHANNEL.receiver();
+    let confirmations = CONFIRM_CHANNEL.receiver();
+    // Confirmations of commands from an earlier connection have nobody waiting for them.
+    CONFIRM_CHANNEL.clear();
 
     loop {
-        match select(conn.next(), receiver.receive()).await {
-            Either::First(event) => {
+        match select3(conn.next(), receiver.receive(), confirmations.receive()).await {
+            Either3::First(event) => {
                 info!("[gatt] Received GATT event");
                 match event {
                     GattConnectionEvent::Disconnected { reason } => {
@@ -222,7 +357,17 @@ async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnecti
                     }
                     GattConnectionEvent::Gatt { event } => {
                         info!("[gatt] Processing GATT request");
-                        match event.accept() {
+                        let refused = match &event {
+                            GattEvent::Write(write) if write.handle() == command.handle => {
+                                queue_command(write.data()).err()
+                            }
+                            _ => None,
+                        };
+                        let reply = match refused {
+                            Some(code) => event.reject(code),
+                            None => event.accept(),
+                        };
+                        match reply {
                             Ok(reply) => {
                                 info!("[gatt] Sending reply");
                                 reply.send().await
@@ -235,14 +380,22 @@ async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnecti
                     }
                 }
             }
-            Either::Second(button_event) => {
+            Either3::Third(opcode) => {
+                let mut payload: Vec<u8, 20> = Vec::new();
+                unwrap!(payload.extend_from_slice(&[opcode, 0]));
+                if let Err(e) = command.notify(conn, &payload).await {
+                    warn!("[command] Failed to confirm command {:#x}: {:?}", opcode, e);
+                }
+            }
+            Either3::Second(button_event) => {
                 info!("[button] Processing button event");
                 match button_event {
                     ButtonEvent::APressed => {
                         info!("[button] Button A pressed, setting state to 1");
-                        unwrap!(button_state.set(server, &1));
+                        let payload = button_payload(1);
+                        unwrap!(button_state.set(server, &payload));
                         info!("[button] Attempting to notify button A press");
-                        if let Err(e) = button_state.notify(conn, &1).await {
+                        if let Err(e) = button_state.notify(conn, &payload).await {
                             warn!("[button] Failed to notify button A press: {:?}", e);
                         } else {
                             info!("[button] Successfully notified button A press");
@@ -250,8 +403,9 @@ async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnecti
                     }
                     ButtonEvent::AReleased => {
                         info!("[button] Button A released, setting state to 0");
-                        unwrap!(button_state.set(server, &0));
-                        if let Err(e) = button_state.notify(conn, &0).await {
+                        let payload = button_payload(0);
+                        unwrap!(button_state.set(server, &payload));
+                        if let Err(e) = button_state.notify(conn, &payload).await {
                             warn!("[button] Failed to notify button A release: {:?}", e);
                         } else {
                             info!("[button] Successfully notified button A release");
@@ -259,8 +413,9 @@ async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnecti
                     }
                     ButtonEvent::BPressed => {
                         info!("[button] Button B pressed, setting state to 2");
-                        unwrap!(button_state.set(server, &2));
-                        if let Err(e) = button_state.notify(conn, &2).await {
+                        let payload = button_payload(2);
+                        unwrap!(button_state.set(server, &payload));
+                        if let Err(e) = button_state.notify(conn, &payload).await {
                             warn!("[button] Failed to notify button B press: {:?}", e);
                         } else {
                             info!("[button] Successfully notified button B press");
@@ -268,8 +423,9 @@ async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnecti
                     }
                     ButtonEvent::BReleased => {
                         info!("[button] Button B released, setting state to 0");
-                        unwrap!(button_state.set(server, &0));
-                        if let Err(e) = button_state.notify(conn, &0).await {
+                        let payload = button_payload(0);
+                        unwrap!(button_state.set(server, &payload));
+                        if let Err(e) = button_state.notify(conn, &payload).await {
                             warn!("[button] Failed to notify button B release: {:?}", e);
                         } else {
                             info!("[button] Successfully notified button B release");
@@ -282,3 +438,17 @@ async fn connection_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnecti
 
     info!("[gatt] task finished");
 }
+
+/// Decodes a write to the command characteristic and queues the command for the command
+/// task. Malformed commands, and commands arriving while the queue is full, are refused
+/// with an ATT error, so the central's write fails and nothing is confirmed.
+fn queue_command(data: &[u8]) -> Result<(), AttErrorCode> {
+    let Some(command) = DeviceCommand::decode(data) else {
+        warn!("[command] Refusing malformed command {:?}", data);
+        return Err(AttErrorCode::VALUE_NOT_ALLOWED);
+    };
+    info!("[command] Queueing command {:#x}", command.opcode());
+    COMMAND_CHANNEL
+        .try_send(command)
+        .map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)
+}
@@ -0,0 +1,302 @@
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::db::Db;
+use crate::event::{ButtonEvent, CONNECTION_BUTTON};
+use crate::registry::Registry;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// No events or heartbeats within the inactivity period.
+    Offline,
+    /// Reported battery level below the threshold.
+    LowBattery,
+}
+
+impl AlertKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Offline => "offline",
+            Self::LowBattery => "low_battery",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "offline" => Some(Self::Offline),
+            "low_battery" => Some(Self::LowBattery),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Alert {
+    pub id: i64,
+    pub device: String,
+    pub kind: AlertKind,
+    pub message: String,
+    pub raised_at: u64,
+    /// Set once the condition has cleared.
+    pub resolved_at: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlertSettings {
+    pub inactivity_ms: u64,
+    pub low_battery: u8,
+}
+
+const COLUMNS: &str = "id, device, kind, message, raised_at, resolved_at";
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Alert> {
+    let kind: String = row.get(2)?;
+    Ok(Alert {
+        id: row.get(0)?,
+        device: row.get(1)?,
+        kind: AlertKind::parse(&kind).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                2,
+                rusqlite::types::Type::Text,
+                format!("unknown alert kind {}", kind).into(),
+            )
+        })?,
+        message: row.get(3)?,
+        raised_at: row.get(4)?,
+        resolved_at: row.get(5)?,
+    })
+}
+
+struct Inner {
+    settings: AlertSettings,
+    /// Server time each device was last heard from.
+    last_heard: HashMap<String, u64>,
+    /// Ids of the unresolved alerts.
+    active: HashMap<(String, AlertKind), i64>,
+}
+
+/// Raises and resolves device alerts and keeps their log in the `alerts` table.
+#[derive(Clone)]
+pub struct Alerts {
+    db: Db,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Alerts {
+    /// Picks up alerts left unresolved by a previous run so they can still resolve, and
+    /// starts each known device's inactivity period from its registry `last_seen`.
+    pub fn load(db: Db, settings: AlertSettings) -> rusqlite::Result<Self> {
+        let mut inner = Inner {
+            settings,
+            last_heard: HashMap::new(),
+            active: HashMap::new(),
+        };
+        for device in Registry::new(db.clone()).list()? {
+            inner.last_heard.insert(device.address, device.last_seen);
+        }
+        for alert in Self::query(&db, true)? {
+            inner.active.insert((alert.device, alert.kind), alert.id);
+        }
+        Ok(Self {
+            db,
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Replaces the thresholds; they apply from the next event or sweep on.
+    pub fn set_settings(&self, settings: AlertSettings) {
+        self.lock().settings = settings;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn query(db: &Db, active_only: bool) -> rusqlite::Result<Vec<Alert>> {
+        let conn = db.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM alerts WHERE ?1 = 0 OR resolved_at IS NULL ORDER BY id DESC",
+            COLUMNS
+        ))?;
+        let alerts = stmt.query_map([active_only], from_row)?.collect();
+        alerts
+    }
+
+    /// Alerts, newest first.
+    pub fn list(&self, active_only: bool) -> rusqlite::Result<Vec<Alert>> {
+        Self::query(&self.db, active_only)
+    }
+
+    fn get(&self, id: i64) -> rusqlite::Result<Option<Alert>> {
+        self.db
+            .conn()
+            .query_row(
+                &format!("SELECT {} FROM alerts WHERE id = ?1", COLUMNS),
+                [id],
+                from_row,
+            )
+            .optional()
+    }
+
+    fn raise(
+        &self,
+        inner: &mut Inner,
+        device: &str,
+        kind: AlertKind,
+        message: String,
+        now: u64,
+    ) -> rusqlite::Result<Option<Alert>> {
+        let key = (device.to_string(), kind);
+        if inner.active.contains_key(&key) {
+            return Ok(None);
+        }
+        let id = {
+            let conn = self.db.conn();
+            conn.execute(
+                "INSERT INTO alerts (device, kind, message, raised_at) VALUES (?1, ?2, ?3, ?4)",
+                params![device, kind.as_str(), message, now],
+            )?;
+            conn.last_insert_rowid()
+        };
+        inner.active.insert(key, id);
+        self.get(id)
+    }
+
+    fn resolve(
+        &self,
+        inner: &mut Inner,
+        device: &str,
+        kind: AlertKind,
+        now: u64,
+    ) -> rusqlite::Result<Option<Alert>> {
+        let Some(id) = inner.active.remove(&(device.to_string(), kind)) else {
+            return Ok(None);
+        };
+        self.db.conn().execute(
+            "UPDATE alerts SET resolved_at = ?2 WHERE id = ?1",
+            params![id, now],
+        )?;
+        self.get(id)
+    }
+
+    /// Updates the alert state of a device from one of its events. Returns the alerts the
+    /// event raised or resolved. A listener reporting that it lost the device is not
+    /// traffic from the device, so it neither counts as activity nor clears `Offline`.
+    pub fn observe(
+        &self,
+        device: &str,
+        event: &ButtonEvent,
+        now: u64,
+    ) -> rusqlite::Result<Vec<Alert>> {
+        let mut inner = self.lock();
+        let mut changed = Vec::new();
+        let disconnected = event.button == CONNECTION_BUTTON && event.state == "DISCONNECTED";
+        if !disconnected {
+            inner.last_heard.insert(device.to_string(), now);
+            changed.extend(self.resolve(&mut inner, device, AlertKind::Offline, now)?);
+        }
+        if let Some(level) = event.battery {
+            let alert = if level < inner.settings.low_battery {
+                let message = format!("Battery at {}%", level);
+                self.raise(&mut inner, device, AlertKind::LowBattery, message, now)?
+            } else {
+                self.resolve(&mut inner, device, AlertKind::LowBattery, now)?
+            };
+            changed.extend(alert);
+        }
+        Ok(changed)
+    }
+
+    /// Raises offline alerts for devices silent for longer than the inactivity period.
+    pub fn sweep(&self, now: u64) -> rusqlite::Result<Vec<Alert>> {
+        let mut inner = self.lock();
+        let inactivity_ms = inner.settings.inactivity_ms;
+        let silent: Vec<(String, u64)> = inner
+            .last_heard
+            .iter()
+            .filter(|(_, &heard)| now.saturating_sub(heard) > inactivity_ms)
+            .map(|(device, &heard)| (device.clone(), heard))
+            .collect();
+
+        let mut raised = Vec::new();
+        for (device, heard) in silent {
+            let message = format!("No events for {} s", now.saturating_sub(heard) / 1000);
+            raised.extend(self.raise(&mut inner, &device, AlertKind::Offline, message, now)?);
+        }
+        Ok(raised)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: AlertSettings = AlertSettings {
+        inactivity_ms: 60_000,
+        low_battery: 20,
+    };
+
+    fn event(button: &str, state: &str, battery: Option<u8>) -> ButtonEvent {
+        ButtonEvent {
+            button: button.to_string(),
+            state: state.to_string(),
+            timestamp: 0,
+            device: None,
+            battery,
+            duration_ms: None,
+            alias: None,
+            trace: None,
+        }
+    }
+
+    fn kinds(alerts: &[Alert]) -> Vec<(AlertKind, bool)> {
+        alerts
+            .iter()
+            .map(|a| (a.kind, a.resolved_at.is_some()))
+            .collect()
+    }
+
+    #[test]
+    fn raises_and_clears_alerts() {
+        let alerts = Alerts::load(Db::open(":memory:").unwrap(), SETTINGS).unwrap();
+
+        let low = alerts.observe("AA", &event("BATTERY", "REPORTED", Some(10)), 0);
+        assert_eq!(kinds(&low.unwrap()), [(AlertKind::LowBattery, false)]);
+        let charged = alerts.observe("AA", &event("BATTERY", "REPORTED", Some(80)), 1_000);
+        assert_eq!(kinds(&charged.unwrap()), [(AlertKind::LowBattery, true)]);
+
+        assert!(alerts.sweep(61_000).unwrap().is_empty());
+        assert_eq!(
+            kinds(&alerts.sweep(61_001).unwrap()),
+            [(AlertKind::Offline, false)]
+        );
+
+        let lost = alerts.observe(
+            "AA",
+            &event(CONNECTION_BUTTON, "DISCONNECTED", None),
+            62_000,
+        );
+        assert!(lost.unwrap().is_empty());
+        assert_eq!(alerts.list(true).unwrap().len(), 1);
+
+        let pressed = alerts.observe("AA", &event("A", "PRESSED", None), 63_000);
+        assert_eq!(kinds(&pressed.unwrap()), [(AlertKind::Offline, true)]);
+        assert!(alerts.list(true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn devices_seen_before_a_restart_can_go_offline() {
+        let db = Db::open(":memory:").unwrap();
+        Registry::new(db.clone()).touch("AA", 5_000).unwrap();
+
+        let alerts = Alerts::load(db, SETTINGS).unwrap();
+        assert!(alerts.sweep(65_000).unwrap().is_empty());
+        let raised = alerts.sweep(65_001).unwrap();
+        assert_eq!(raised[0].device, "AA");
+        assert_eq!(kinds(&raised), [(AlertKind::Offline, false)]);
+    }
+}
@@ -0,0 +1,268 @@
+use axum::{middleware, routing::get, Router};
+use tower_http::services::ServeDir;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_axum::{router::OpenApiRouter, routes};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::audit;
+use crate::config::SESSION_COOKIE;
+use crate::handlers;
+use crate::ratelimit;
+use crate::state::AppState;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "ws-server",
+        description = "Broadcasts micro:bit button events to WebSocket clients."
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "auth", description = "Login sessions and user accounts"),
+        (name = "events", description = "Event ingest and export"),
+        (name = "devices", description = "Device registry and live state"),
+        (name = "rooms", description = "Rooms and their broadcast channels"),
+        (name = "commands", description = "Downlink commands"),
+        (name = "alerts", description = "Device alerts"),
+        (name = "audit", description = "Log of mutating requests"),
+        (name = "admin", description = "Server administration"),
+        (name = "metrics", description = "Latency metrics"),
+        (name = "websocket", description = "Live event stream")
+    )
+)]
+pub struct ApiDoc;
+
+/// `session` is the login cookie; `ingest_token` is the bearer token ble-listener sends.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(SESSION_COOKIE))),
+        );
+        components.add_security_scheme(
+            "ingest_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Routes described by the OpenAPI document. Every route added here is documented by the
+/// `#[utoipa::path]` attribute of its handler.
+fn documented_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(handlers::login_page, handlers::login))
+        .routes(routes!(handlers::logout))
+        .routes(routes!(handlers::me))
+        .routes(routes!(handlers::list_users, handlers::create_user))
+        .routes(routes!(handlers::update_user, handlers::delete_user))
+        .routes(routes!(handlers::websocket_handler))
+        .routes(routes!(handlers::websocket_room_handler))
+        .routes(routes!(handlers::button_event))
+        .routes(routes!(handlers::button_stream))
+        .routes(routes!(handlers::export_events))
+        .routes(routes!(handlers::list_devices, handlers::create_device))
+        .routes(routes!(
+            handlers::get_device,
+            handlers::update_device,
+            handlers::delete_device
+        ))
+        .routes(routes!(handlers::device_state))
+        .routes(routes!(handlers::list_rooms, handlers::create_room))
+        .routes(routes!(handlers::delete_room))
+        .routes(routes!(handlers::list_commands, handlers::create_command))
+        .routes(routes!(handlers::pull_commands))
+        .routes(routes!(handlers::report_command))
+        .routes(routes!(handlers::list_alerts))
+        .routes(routes!(handlers::list_webhooks, handlers::create_webhook))
+        .routes(routes!(handlers::delete_webhook))
+        .routes(routes!(handlers::list_audit))
+        .routes(routes!(handlers::verify_audit))
+        .routes(routes!(handlers::reload_settings))
+        .routes(routes!(handlers::latency_report))
+        .routes(routes!(handlers::metrics))
+}
+
+pub fn router(state: AppState) -> Router {
+    let (api, openapi) = documented_routes().split_for_parts();
+
+    Router::new()
+        .route("/", get(handlers::serve_html))
+        .merge(api)
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", openapi))
+        .nest_service("/pkg", ServeDir::new("pkg"))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit::audit_requests,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ratelimit::limit_requests,
+        ))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request, StatusCode};
+    use tower::ServiceExt;
+    use utoipa::openapi::HttpMethod;
+
+    use crate::alerts::AlertSettings;
+    use crate::auth::{NewUser, Role};
+    use crate::db::Db;
+
+    const METHODS: [(HttpMethod, Method); 5] = [
+        (HttpMethod::Get, Method::GET),
+        (HttpMethod::Post, Method::POST),
+        (HttpMethod::Put, Method::PUT),
+        (HttpMethod::Patch, Method::PATCH),
+        (HttpMethod::Delete, Method::DELETE),
+    ];
+
+    /// Returned for paths the router does not know, so it cannot be confused with a
+    /// handler answering 404 for a missing device.
+    const UNROUTED: StatusCode = StatusCode::MISDIRECTED_REQUEST;
+
+    fn test_state() -> AppState {
+        let db = Db::open(":memory:").unwrap();
+        let alerts = AlertSettings {
+            inactivity_ms: 60_000,
+            low_battery: 20,
+        };
+        AppState::new(16, db, alerts, None).unwrap()
+    }
+
+    fn test_router() -> Router {
+        router(test_state()).fallback(|| async { UNROUTED })
+    }
+
+    async fn status(router: &Router, method: Method, path: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn spec_and_routes_agree() {
+        let router = test_router();
+        let (_, openapi) = documented_routes().split_for_parts();
+
+        for (path, item) in &openapi.paths.paths {
+            let concrete = path
+                .replace("{id}", "1")
+                .replace("{room}", "x")
+                .replace("{name}", "x");
+            for (spec_method, method) in METHODS {
+                let documented = match spec_method {
+                    HttpMethod::Get => item.get.is_some(),
+                    HttpMethod::Post => item.post.is_some(),
+                    HttpMethod::Put => item.put.is_some(),
+                    HttpMethod::Patch => item.patch.is_some(),
+                    HttpMethod::Delete => item.delete.is_some(),
+                    _ => unreachable!(),
+                };
+                let status = status(&router, method.clone(), &concrete).await;
+                if documented {
+                    assert!(
+                        status != UNROUTED && status != StatusCode::METHOD_NOT_ALLOWED,
+                        "{} {} is documented but not routed ({})",
+                        method,
+                        path,
+                        status
+                    );
+                } else {
+                    assert_eq!(
+                        status,
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "{} {} is routed but not documented",
+                        method,
+                        path
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_openapi_document() {
+        let router = test_router();
+        assert_eq!(
+            status(&router, Method::GET, "/api/openapi.json").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, Method::GET, "/api/docs/").await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn roles_are_enforced() {
+        let state = test_state();
+        for (username, role) in [("viewer", Role::Viewer), ("admin", Role::Admin)] {
+            let user = NewUser {
+                username: username.to_string(),
+                password: "secret".to_string(),
+                role,
+            };
+            state.users.create(user, 0).unwrap();
+        }
+        let router = router(state);
+
+        let login = |username: &str| {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("/login")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(format!("username={}&password=secret", username)))
+                .unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::SEE_OTHER);
+                let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+                cookie.split(';').next().unwrap().to_string()
+            }
+        };
+        let request = |method: Method, path: &str, cookie: Option<&str>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            let request = request.body(Body::from(r#"{"address":"AA"}"#)).unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        let viewer = login("viewer").await;
+        let admin = login("admin").await;
+        assert_eq!(
+            request(Method::GET, "/api/devices", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            request(Method::GET, "/api/devices", Some(&viewer)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            request(Method::POST, "/api/devices", Some(&viewer)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            request(Method::POST, "/api/devices", Some(&admin)).await,
+            StatusCode::CREATED
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{header, request::Parts, Extensions, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use utoipa::ToSchema;
+
+use crate::auth::current_user;
+use crate::config::{AUDIT_BODY_LIMIT, AUDIT_SKIPPED_PATHS};
+use crate::db::Db;
+use crate::event::now_millis;
+use crate::logging::error;
+use crate::state::AppState;
+
+/// `prev_hash` of the first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One audited action as stored in the `audit` table.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: u64,
+    /// Username of the session, `ingest` for the ingest token, the submitted username for
+    /// logins, or `null` if anonymous.
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    /// HTTP method, or `WS` for a message received over a WebSocket.
+    pub method: String,
+    pub path: String,
+    /// Response status; for WebSocket messages the status an equivalent HTTP request
+    /// would have had.
+    pub status: u16,
+    /// Hex SHA-256 of the request body; `null` for empty bodies and login forms.
+    pub payload_hash: Option<String>,
+    pub prev_hash: String,
+    /// Hex SHA-256 over `prev_hash` and every other field of this entry.
+    pub hash: String,
+}
+
+/// What is known about an action before it is chained into the log.
+#[derive(Clone, Debug)]
+pub struct AuditRecord {
+    pub at: u64,
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub payload_hash: Option<String>,
+}
+
+/// Selects audit entries; every bound is optional and inclusive.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub actor: Option<String>,
+}
+
+/// Result of re-computing the hash chain.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditVerification {
+    pub valid: bool,
+    /// Number of entries checked.
+    pub entries: u64,
+    /// First entry whose id, `prev_hash` or `hash` does not match.
+    pub broken_at: Option<i64>,
+    /// Hash of the last entry. Removing entries from the end of the log keeps the chain
+    /// valid, so keep a copy of it elsewhere to detect truncation.
+    pub head: Option<String>,
+}
+
+const COLUMNS: &str = "id, at, actor, ip, method, path, status, payload_hash, prev_hash, hash";
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        at: row.get(1)?,
+        actor: row.get(2)?,
+        ip: row.get(3)?,
+        method: row.get(4)?,
+        path: row.get(5)?,
+        status: row.get(6)?,
+        payload_hash: row.get(7)?,
+        prev_hash: row.get(8)?,
+        hash: row.get(9)?,
+    })
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn entry_hash(prev_hash: &str, id: i64, record: &AuditRecord) -> String {
+    let fields = serde_json::json!([
+        prev_hash,
+        id,
+        record.at,
+        record.actor,
+        record.ip,
+        record.method,
+        record.path,
+        record.status,
+        record.payload_hash,
+    ]);
+    sha256_hex(fields.to_string().as_bytes())
+}
+
+/// Append-only, hash-chained log of mutating requests.
+#[derive(Clone)]
+pub struct AuditLog {
+    db: Db,
+}
+
+impl AuditLog {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+
+    /// Chains the record onto the last entry. The connection stays locked between reading
+    /// the previous hash and inserting, so concurrent appends cannot fork the chain.
+    pub fn append(&self, record: AuditRecord) -> rusqlite::Result<AuditEntry> {
+        let conn = self.db.conn();
+        let last: Option<(i64, String)> = conn
+            .query_row(
+                "SELECT id, hash FROM audit ORDER BY id DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let (id, prev_hash) = match last {
+            Some((id, hash)) => (id + 1, hash),
+            None => (1, GENESIS_HASH.to_string()),
+        };
+        let hash = entry_hash(&prev_hash, id, &record);
+        conn.execute(
+            &format!(
+                "INSERT INTO audit ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                COLUMNS
+            ),
+            params![
+                id,
+                record.at,
+                record.actor,
+                record.ip,
+                record.method,
+                record.path,
+                record.status,
+                record.payload_hash,
+                prev_hash,
+                hash
+            ],
+        )?;
+        Ok(AuditEntry {
+            id,
+            at: record.at,
+            actor: record.actor,
+            ip: record.ip,
+            method: record.method,
+            path: record.path,
+            status: record.status,
+            payload_hash: record.payload_hash,
+            prev_hash,
+            hash,
+        })
+    }
+
+    /// Logs instead of failing: an audit error must not undo an action that already ran.
+    pub fn record(&self, record: AuditRecord) {
+        if let Err(e) = self.append(record) {
+            error!("Failed to write audit entry: {}", e);
+        }
+    }
+
+    /// Returns up to `limit` matching entries with an id above `after`, in id order.
+    pub fn page(
+        &self,
+        filter: &AuditFilter,
+        after: i64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<AuditEntry>> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT {} FROM audit
+             WHERE id > ?1
+               AND (?2 IS NULL OR at >= ?2)
+               AND (?3 IS NULL OR at <= ?3)
+               AND (?4 IS NULL OR actor = ?4)
+             ORDER BY id
+             LIMIT ?5",
+            COLUMNS
+        ))?;
+        let entries = stmt
+            .query_map(
+                params![after, filter.from, filter.to, filter.actor, limit],
+                from_row,
+            )?
+            .collect();
+        entries
+    }
+
+    /// Walks the whole chain, checking that every entry links to its predecessor and that
+    /// its hash matches its contents.
+    pub fn verify(&self) -> rusqlite::Result<AuditVerification> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM audit ORDER BY id", COLUMNS))?;
+        let mut rows = stmt.query([])?;
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut expected_id = 1;
+        let mut entries = 0;
+        while let Some(row) = rows.next()? {
+            let entry = from_row(row)?;
+            entries += 1;
+            let record = AuditRecord {
+                at: entry.at,
+                actor: entry.actor,
+                ip: entry.ip,
+                method: entry.method,
+                path: entry.path,
+                status: entry.status,
+                payload_hash: entry.payload_hash,
+            };
+            if entry.id != expected_id
+                || entry.prev_hash != prev_hash
+                || entry.hash != entry_hash(&prev_hash, entry.id, &record)
+            {
+                return Ok(AuditVerification {
+                    valid: false,
+                    entries,
+                    broken_at: Some(entry.id),
+                    head: None,
+                });
+            }
+            prev_hash = entry.hash;
+            expected_id += 1;
+        }
+        Ok(AuditVerification {
+            valid: true,
+            entries,
+            broken_at: None,
+            head: (entries > 0).then_some(prev_hash),
+        })
+    }
+}
+
+/// Peer address, if the server was started with connection info.
+pub fn client_ip(extensions: &Extensions) -> Option<String> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+}
+
+/// Response extension naming the actor of a request made without a session, such as a
+/// login attempt.
+#[derive(Clone)]
+pub struct AuditActor(pub String);
+
+/// Extracts the peer address for handlers that audit actions themselves.
+pub struct ClientIp(pub Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(client_ip(&parts.extensions)))
+    }
+}
+
+/// Middleware recording every POST, PUT, PATCH and DELETE request with its outcome.
+pub async fn audit_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let mutating = matches!(
+        method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    );
+    if !mutating || AUDIT_SKIPPED_PATHS.contains(&path.as_str()) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let actor = current_user(&state, &parts.headers)
+        .map(|user| user.username)
+        .or_else(|| {
+            let token = state.ingest_token.as_deref()?;
+            let bearer = parts.headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+            (bearer.strip_prefix("Bearer ") == Some(token)).then(|| "ingest".to_string())
+        });
+    let ip = client_ip(&parts.extensions);
+    let body = match to_bytes(body, AUDIT_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response(),
+    };
+    // A hash of a login form would let anyone holding the log guess passwords offline.
+    let payload_hash = (!body.is_empty() && path != "/login").then(|| sha256_hex(&body));
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    let actor = actor.or_else(|| {
+        let AuditActor(actor) = response.extensions().get::<AuditActor>()?;
+        Some(actor.clone())
+    });
+    state.audit.record(AuditRecord {
+        at: now_millis(),
+        actor,
+        ip,
+        method: method.to_string(),
+        path,
+        status: response.status().as_u16(),
+        payload_hash,
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(path: &str) -> AuditRecord {
+        AuditRecord {
+            at: 1,
+            actor: Some("admin".to_string()),
+            ip: Some("127.0.0.1".to_string()),
+            method: "DELETE".to_string(),
+            path: path.to_string(),
+            status: 204,
+            payload_hash: None,
+        }
+    }
+
+    #[test]
+    fn tampering_breaks_the_chain() {
+        let db = Db::open(":memory:").unwrap();
+        let audit = AuditLog::new(db.clone());
+        let first = audit.append(record("/api/devices/AA")).unwrap();
+        let second = audit.append(record("/api/devices/BB")).unwrap();
+        audit.append(record("/api/devices/CC")).unwrap();
+        assert_eq!(second.prev_hash, first.hash);
+        assert!(audit.verify().unwrap().valid);
+
+        db.conn()
+            .execute("UPDATE audit SET path = '/api/rooms/x' WHERE id = 2", [])
+            .unwrap();
+        let result = audit.verify().unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.broken_at, Some(2));
+
+        db.conn()
+            .execute("UPDATE audit SET path = '/api/devices/BB' WHERE id = 2", [])
+            .unwrap();
+        db.conn()
+            .execute("DELETE FROM audit WHERE id = 2", [])
+            .unwrap();
+        assert_eq!(audit.verify().unwrap().broken_at, Some(3));
+    }
+}
@@ -0,0 +1,381 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts, HeaderMap, StatusCode};
+use rand_core::{OsRng, RngCore};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use utoipa::ToSchema;
+
+use crate::config::{SESSION_COOKIE, SESSION_TTL_MS};
+use crate::db::Db;
+use crate::event::now_millis;
+use crate::logging::error;
+use crate::state::AppState;
+
+/// Roles are ordered: every role may do everything the roles below it may.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Watch the dashboard and read the API.
+    Viewer,
+    /// Also send device commands.
+    Operator,
+    /// Also manage devices, rooms, webhooks and users.
+    Admin,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Admin => "admin",
+        }
+    }
+
+    fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Self::Viewer),
+            "operator" => Some(Self::Operator),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct User {
+    pub username: String,
+    pub role: Role,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+}
+
+/// Fields to change on an existing user; omitted fields are left untouched.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UserPatch {
+    pub password: Option<String>,
+    pub role: Option<Role>,
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<User> {
+    let role: String = row.get(1)?;
+    Ok(User {
+        username: row.get(0)?,
+        role: Role::parse(&role).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                1,
+                rusqlite::types::Type::Text,
+                format!("unknown role {}", role).into(),
+            )
+        })?,
+        created_at: row.get(2)?,
+    })
+}
+
+fn hash_password(password: &str) -> rusqlite::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.to_string().into()))
+}
+
+/// Argon2 hash of a password no account has, with the default parameters. Logins for an
+/// unknown username are checked against it, so they take as long as a wrong password and
+/// the response time does not reveal which usernames exist.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$zjPB/p00+1McaOKtcV2TGg$ZR7U76TIbnUEieoTTUGKGjHQdNw5G6rYOPOQg3+2qr8";
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Random hex string, used for session tokens and generated passwords.
+pub fn random_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+/// User accounts and login sessions, stored in the `users` and `sessions` tables.
+#[derive(Clone)]
+pub struct Users {
+    db: Db,
+}
+
+impl Users {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+
+    pub fn list(&self) -> rusqlite::Result<Vec<User>> {
+        let conn = self.db.conn();
+        let mut stmt =
+            conn.prepare("SELECT username, role, created_at FROM users ORDER BY username")?;
+        let users = stmt.query_map([], from_row)?.collect();
+        users
+    }
+
+    pub fn get(&self, username: &str) -> rusqlite::Result<Option<User>> {
+        self.db
+            .conn()
+            .query_row(
+                "SELECT username, role, created_at FROM users WHERE username = ?1",
+                [username],
+                from_row,
+            )
+            .optional()
+    }
+
+    /// Creates a user. Returns `None` if the username is taken.
+    pub fn create(&self, user: NewUser, now: u64) -> rusqlite::Result<Option<User>> {
+        let hash = hash_password(&user.password)?;
+        let inserted = self.db.conn().execute(
+            "INSERT OR IGNORE INTO users (username, password_hash, role, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![user.username, hash, user.role.as_str(), now],
+        )?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        self.get(&user.username)
+    }
+
+    /// Changing the password or role ends the user's sessions.
+    pub fn update(&self, username: &str, patch: UserPatch) -> rusqlite::Result<Option<User>> {
+        let hash = patch.password.as_deref().map(hash_password).transpose()?;
+        let updated = self.db.conn().execute(
+            "UPDATE users SET
+                password_hash = COALESCE(?2, password_hash),
+                role = COALESCE(?3, role)
+             WHERE username = ?1",
+            params![username, hash, patch.role.map(Role::as_str)],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        self.db
+            .conn()
+            .execute("DELETE FROM sessions WHERE username = ?1", [username])?;
+        self.get(username)
+    }
+
+    pub fn delete(&self, username: &str) -> rusqlite::Result<bool> {
+        let conn = self.db.conn();
+        conn.execute("DELETE FROM sessions WHERE username = ?1", [username])?;
+        let deleted = conn.execute("DELETE FROM users WHERE username = ?1", [username])?;
+        Ok(deleted > 0)
+    }
+
+    /// Checks the credentials and opens a session. Returns its token, or `None` if the
+    /// username or password is wrong.
+    pub fn login(
+        &self,
+        username: &str,
+        password: &str,
+        now: u64,
+    ) -> rusqlite::Result<Option<String>> {
+        let hash: Option<String> = self
+            .db
+            .conn()
+            .query_row(
+                "SELECT password_hash FROM users WHERE username = ?1",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(hash) = hash else {
+            verify_password(password, DUMMY_PASSWORD_HASH);
+            return Ok(None);
+        };
+        if !verify_password(password, &hash) {
+            return Ok(None);
+        }
+        let token = random_token(32);
+        let conn = self.db.conn();
+        conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", [now])?;
+        conn.execute(
+            "INSERT INTO sessions (token, username, expires_at) VALUES (?1, ?2, ?3)",
+            params![token, username, now + SESSION_TTL_MS],
+        )?;
+        Ok(Some(token))
+    }
+
+    pub fn logout(&self, token: &str) -> rusqlite::Result<()> {
+        self.db
+            .conn()
+            .execute("DELETE FROM sessions WHERE token = ?1", [token])?;
+        Ok(())
+    }
+
+    /// The user owning an unexpired session.
+    pub fn session_user(&self, token: &str, now: u64) -> rusqlite::Result<Option<User>> {
+        self.db
+            .conn()
+            .query_row(
+                "SELECT u.username, u.role, u.created_at FROM sessions s
+                 JOIN users u ON u.username = s.username
+                 WHERE s.token = ?1 AND s.expires_at > ?2",
+                params![token, now],
+                from_row,
+            )
+            .optional()
+    }
+
+    /// Creates the `admin` account if there are no users yet. Returns the password if one
+    /// had to be generated.
+    pub fn bootstrap(
+        &self,
+        password: Option<String>,
+        now: u64,
+    ) -> rusqlite::Result<Option<String>> {
+        let count: u64 = self
+            .db
+            .conn()
+            .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+        if count > 0 {
+            return Ok(None);
+        }
+        let (password, generated) = match password {
+            Some(password) => (password, false),
+            None => (random_token(12), true),
+        };
+        self.create(
+            NewUser {
+                username: "admin".to_string(),
+                password: password.clone(),
+                role: Role::Admin,
+            },
+            now,
+        )?;
+        Ok(generated.then_some(password))
+    }
+}
+
+/// Value of the session cookie, if the request carries one.
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// The logged-in user of a request.
+pub fn current_user(state: &AppState, headers: &HeaderMap) -> Option<User> {
+    let token = session_token(headers)?;
+    match state.users.session_user(token, now_millis()) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Failed to look up session: {}", e);
+            None
+        }
+    }
+}
+
+/// Marker for the least role an [`Auth`] extractor accepts.
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct Viewer;
+pub struct Operator;
+pub struct Admin;
+
+impl RequiredRole for Viewer {
+    const ROLE: Role = Role::Viewer;
+}
+
+impl RequiredRole for Operator {
+    const ROLE: Role = Role::Operator;
+}
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Extracts the logged-in user, rejecting the request with 401 if there is no valid
+/// session and 403 if the user's role is below `R`.
+pub struct Auth<R>(pub User, pub PhantomData<R>);
+
+impl<R: RequiredRole> FromRequestParts<AppState> for Auth<R> {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = current_user(state, &parts.headers)
+            .ok_or((StatusCode::UNAUTHORIZED, "Login required"))?;
+        if user.role < R::ROLE {
+            return Err((StatusCode::FORBIDDEN, "Insufficient role"));
+        }
+        Ok(Self(user, PhantomData))
+    }
+}
+
+/// Guards the endpoints used by ble-listener. When an ingest token is configured the
+/// request must carry it as a bearer token or come from an operator session; otherwise
+/// these endpoints stay open.
+pub struct IngestAuth;
+
+impl FromRequestParts<AppState> for IngestAuth {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(expected) = &state.ingest_token else {
+            return Ok(Self);
+        };
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer == Some(expected.as_str()) {
+            return Ok(Self);
+        }
+        match current_user(state, &parts.headers) {
+            Some(user) if user.role >= Role::Operator => Ok(Self),
+            Some(_) => Err((StatusCode::FORBIDDEN, "Insufficient role")),
+            None => Err((StatusCode::UNAUTHORIZED, "Ingest token required")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_a_real_one() {
+        let dummy = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        let real = hash_password("secret").unwrap();
+        let real = PasswordHash::new(&real).unwrap();
+        assert_eq!(dummy.algorithm, real.algorithm);
+        assert_eq!(
+            argon2::Params::try_from(&dummy).unwrap(),
+            argon2::Params::try_from(&real).unwrap()
+        );
+    }
+}
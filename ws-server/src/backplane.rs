@@ -0,0 +1,217 @@
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::config::BACKPLANE_SUBJECT;
+use crate::event::ServerMessage;
+use crate::logging::{error, warn};
+use crate::state::AppState;
+
+/// A room broadcast as it travels between ws-server instances.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    /// Node that published the message; nodes ignore their own messages.
+    origin: String,
+    room: Option<String>,
+    message: ServerMessage,
+}
+
+/// Publishing side of the NATS backplane shared by several ws-server instances.
+#[derive(Clone)]
+pub struct Backplane {
+    node_id: String,
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl Backplane {
+    /// Connects to NATS and subscribes to the broadcast subject. The subscriber must be
+    /// handed to [`relay`] once the application state exists.
+    pub async fn connect(
+        url: &str,
+        node_id: String,
+    ) -> Result<(Self, async_nats::Subscriber), Box<dyn std::error::Error>> {
+        let client = async_nats::connect(url).await?;
+        let subscriber = client.subscribe(BACKPLANE_SUBJECT).await?;
+
+        let (outgoing, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                if let Err(e) = client.publish(BACKPLANE_SUBJECT, payload.into()).await {
+                    error!("Failed to publish to backplane: {}", e);
+                }
+            }
+        });
+
+        Ok((Self { node_id, outgoing }, subscriber))
+    }
+
+    /// Forwards a local broadcast to the other nodes.
+    pub fn publish(&self, room: Option<&str>, message: &ServerMessage) {
+        let envelope = Envelope {
+            origin: self.node_id.clone(),
+            room: room.map(str::to_string),
+            message: message.clone(),
+        };
+        match serde_json::to_vec(&envelope) {
+            Ok(payload) => {
+                let _ = self.outgoing.send(payload);
+            }
+            Err(e) => error!("Failed to serialize backplane message: {}", e),
+        }
+    }
+}
+
+/// Delivers broadcasts published by other nodes to the local WebSocket clients.
+pub async fn relay(mut subscriber: async_nats::Subscriber, node_id: String, state: AppState) {
+    while let Some(message) = subscriber.next().await {
+        let envelope: Envelope = match serde_json::from_slice(&message.payload) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                warn!("Ignoring invalid backplane message: {}", e);
+                continue;
+            }
+        };
+        if envelope.origin == node_id {
+            continue;
+        }
+        if let ServerMessage::Event(event) = &envelope.message {
+            state.record_event(event).await;
+        }
+        state.rooms.send(envelope.room.as_deref(), envelope.message);
+    }
+    error!("Backplane subscription closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{tcp::OwnedWriteHalf, TcpListener};
+
+    use crate::alerts::AlertSettings;
+    use crate::db::Db;
+    use crate::event::ButtonEvent;
+    use crate::ingest::ingest_event;
+    use crate::rooms::DEFAULT_ROOM;
+
+    type Subscriptions = Arc<Mutex<Vec<(String, Arc<tokio::sync::Mutex<OwnedWriteHalf>>)>>>;
+
+    /// Just enough of the NATS protocol (INFO, CONNECT, PING, SUB, PUB) to relay messages
+    /// between clients on one subject. Returns the `nats://` URL to connect to.
+    async fn stub_broker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let subscriptions: Subscriptions = Arc::default();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (read, write) = stream.into_split();
+                let write = Arc::new(tokio::sync::Mutex::new(write));
+                let subscriptions = subscriptions.clone();
+                tokio::spawn(async move {
+                    let info = format!(
+                        "INFO {{\"server_id\":\"stub\",\"server_name\":\"stub\",\"version\":\"2.10.0\",\"go\":\"go1.22\",\"host\":\"127.0.0.1\",\"port\":{},\"headers\":true,\"max_payload\":1048576,\"proto\":1}}\r\n",
+                        port
+                    );
+                    write.lock().await.write_all(info.as_bytes()).await.unwrap();
+
+                    let mut reader = BufReader::new(read);
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+                        let parts: Vec<&str> = line.split_whitespace().collect();
+                        match parts.as_slice() {
+                            ["PING"] => {
+                                let _ = write.lock().await.write_all(b"PONG\r\n").await;
+                            }
+                            ["SUB", subject, sid] => {
+                                subscriptions
+                                    .lock()
+                                    .unwrap()
+                                    .push((format!("{} {}", subject, sid), write.clone()));
+                            }
+                            ["PUB", subject, len] => {
+                                let mut payload = vec![0; len.parse::<usize>().unwrap() + 2];
+                                reader.read_exact(&mut payload).await.unwrap();
+                                let targets: Vec<_> = subscriptions
+                                    .lock()
+                                    .unwrap()
+                                    .iter()
+                                    .filter(|(sub, _)| sub.starts_with(&format!("{} ", subject)))
+                                    .cloned()
+                                    .collect();
+                                for (sub, target) in targets {
+                                    let mut frame = format!("MSG {} {}\r\n", sub, len).into_bytes();
+                                    frame.extend_from_slice(&payload);
+                                    let _ = target.lock().await.write_all(&frame).await;
+                                }
+                            }
+                            _ => {}
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        format!("nats://127.0.0.1:{}", port)
+    }
+
+    async fn node(url: &str, id: &str) -> AppState {
+        let db = Db::open(":memory:").unwrap();
+        let alerts = AlertSettings {
+            inactivity_ms: 60_000,
+            low_battery: 20,
+        };
+        let mut state = AppState::new(16, db, alerts, None).unwrap();
+        let (backplane, subscriber) = Backplane::connect(url, id.to_string()).await.unwrap();
+        state.backplane = Some(backplane);
+        tokio::spawn(relay(subscriber, id.to_string(), state.clone()));
+        state
+    }
+
+    #[tokio::test]
+    async fn events_reach_clients_on_other_nodes_once() {
+        let url = stub_broker().await;
+        let a = node(&url, "a").await;
+        let b = node(&url, "b").await;
+        let mut a_rx = a.rooms.join(DEFAULT_ROOM, None).unwrap();
+        let mut b_rx = b.rooms.join(DEFAULT_ROOM, None).unwrap();
+        // Give both subscriptions time to reach the broker.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let event = ButtonEvent {
+            button: "A".to_string(),
+            state: "PRESSED".to_string(),
+            timestamp: 1,
+            device: Some("AA".to_string()),
+            battery: None,
+            duration_ms: None,
+            alias: None,
+            trace: None,
+        };
+        ingest_event(&a, event).await;
+
+        let received = tokio::time::timeout(Duration::from_secs(2), b_rx.recv())
+            .await
+            .expect("event did not reach node b")
+            .unwrap();
+        assert!(matches!(received, ServerMessage::Event(e) if e.button == "A"));
+        assert!(b
+            .device_snapshot(DEFAULT_ROOM)
+            .await
+            .iter()
+            .any(|d| d.id == "AA"));
+
+        // Node a delivers its own event locally and must not receive it back.
+        assert!(matches!(
+            a_rx.recv().await.unwrap(),
+            ServerMessage::Event(_)
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(a_rx.try_recv().is_err());
+        assert!(b_rx.try_recv().is_err());
+    }
+}
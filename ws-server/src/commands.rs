@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+/// Actions a device can be asked to perform.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CommandKind {
+    ShowText { text: String },
+    PlayTone { frequency: u16, duration_ms: u16 },
+    ResetCounters,
+}
+
+/// Longest `show_text` text the device firmware accepts, in bytes of UTF-8.
+pub const SHOW_TEXT_MAX_BYTES: usize = 19;
+
+impl CommandKind {
+    /// Checks the arguments against what the device firmware accepts, so a command that
+    /// can only fail is never queued.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::ShowText { text } if text.len() > SHOW_TEXT_MAX_BYTES => Err(format!(
+                "text must be at most {} bytes of UTF-8",
+                SHOW_TEXT_MAX_BYTES
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    /// Waiting for a listener to pick it up.
+    Queued,
+    /// Handed to a listener, waiting for the device to confirm it.
+    Delivered,
+    /// The device confirmed it ran the command.
+    Acknowledged,
+    Failed,
+    TimedOut,
+}
+
+impl CommandStatus {
+    pub fn is_final(self) -> bool {
+        matches!(self, Self::Acknowledged | Self::Failed | Self::TimedOut)
+    }
+
+    /// How far along the lifecycle the status is; a command never moves to a lower stage.
+    fn stage(self) -> u8 {
+        match self {
+            Self::Queued => 0,
+            Self::Delivered => 1,
+            Self::Acknowledged | Self::Failed | Self::TimedOut => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Command {
+    pub id: u64,
+    pub device: String,
+    #[serde(flatten)]
+    pub command: CommandKind,
+    pub status: CommandStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: u64,
+    /// When a listener took the command; its timeout runs from here once set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<u64>,
+    pub updated_at: u64,
+}
+
+/// Why a listener report was not recorded.
+#[derive(Debug, PartialEq)]
+pub enum ReportError {
+    /// No command with that id, or it has already finished.
+    NotPending,
+    /// The report would move the command back, e.g. from `delivered` to `queued`.
+    Backwards,
+}
+
+/// Status report sent back by a listener.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CommandReport {
+    pub status: CommandStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    commands: BTreeMap<u64, Command>,
+}
+
+/// In-memory queue of downlink commands, shared between the HTTP and WebSocket handlers.
+#[derive(Clone, Default)]
+pub struct CommandQueue {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl CommandQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn enqueue(&self, device: &str, command: CommandKind, now: u64) -> Command {
+        let mut inner = self.lock();
+        inner.next_id += 1;
+        let command = Command {
+            id: inner.next_id,
+            device: device.to_string(),
+            command,
+            status: CommandStatus::Queued,
+            error: None,
+            created_at: now,
+            delivered_at: None,
+            updated_at: now,
+        };
+        inner.commands.insert(command.id, command.clone());
+        command
+    }
+
+    pub fn for_device(&self, device: &str) -> Vec<Command> {
+        self.lock()
+            .commands
+            .values()
+            .filter(|c| c.device == device)
+            .cloned()
+            .collect()
+    }
+
+    /// Hands every queued command for `device` to the caller, marking them delivered.
+    pub fn take_pending(&self, device: &str, now: u64) -> Vec<Command> {
+        let mut inner = self.lock();
+        inner
+            .commands
+            .values_mut()
+            .filter(|c| c.device == device && c.status == CommandStatus::Queued)
+            .map(|c| {
+                c.status = CommandStatus::Delivered;
+                c.delivered_at = Some(now);
+                c.updated_at = now;
+                c.clone()
+            })
+            .collect()
+    }
+
+    /// Records a listener report. Unknown and finished commands are not pending, and a
+    /// report may not move a command back to an earlier status.
+    pub fn report(&self, id: u64, report: CommandReport, now: u64) -> Result<Command, ReportError> {
+        let mut inner = self.lock();
+        let command = inner
+            .commands
+            .get_mut(&id)
+            .filter(|c| !c.status.is_final())
+            .ok_or(ReportError::NotPending)?;
+        if report.status.stage() < command.status.stage() {
+            return Err(ReportError::Backwards);
+        }
+        command.status = report.status;
+        command.error = report.error;
+        command.updated_at = now;
+        Ok(command.clone())
+    }
+
+    /// Times out commands that have not reached a final status within `timeout_ms` of
+    /// being delivered, or of being created if no listener took them, and forgets finished
+    /// commands older than `retention_ms`. Status reports do not extend the timeout.
+    pub fn expire(&self, now: u64, timeout_ms: u64, retention_ms: u64) -> Vec<Command> {
+        let mut inner = self.lock();
+        inner
+            .commands
+            .retain(|_, c| !c.status.is_final() || now.saturating_sub(c.updated_at) < retention_ms);
+        inner
+            .commands
+            .values_mut()
+            .filter(|c| {
+                let since = c.delivered_at.unwrap_or(c.created_at);
+                !c.status.is_final() && now.saturating_sub(since) > timeout_ms
+            })
+            .map(|c| {
+                c.status = CommandStatus::TimedOut;
+                c.updated_at = now;
+                c.clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(status: CommandStatus) -> CommandReport {
+        CommandReport {
+            status,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn show_text_is_limited_to_what_the_device_accepts() {
+        let text = |text: &str| CommandKind::ShowText {
+            text: text.to_string(),
+        };
+        assert!(text("HELLO WORLD 1234567").validate().is_ok());
+        assert!(text("HELLO WORLD 12345678").validate().is_err());
+        // Ten two-byte characters are 20 bytes, even though they are only ten characters.
+        assert!(text("éééééééééé").validate().is_err());
+        assert!(CommandKind::ResetCounters.validate().is_ok());
+    }
+
+    #[test]
+    fn reports_only_move_forward_and_do_not_extend_the_timeout() {
+        let queue = CommandQueue::default();
+        let command = queue.enqueue("AA", CommandKind::ResetCounters, 0);
+        queue.take_pending("AA", 1_000);
+
+        assert_eq!(
+            queue
+                .report(command.id, report(CommandStatus::Queued), 2_000)
+                .unwrap_err(),
+            ReportError::Backwards
+        );
+        let reported = queue.report(command.id, report(CommandStatus::Delivered), 20_000);
+        assert_eq!(reported.unwrap().status, CommandStatus::Delivered);
+
+        let timed_out = queue.expire(31_500, 30_000, 60_000);
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].status, CommandStatus::TimedOut);
+        assert_eq!(
+            queue
+                .report(command.id, report(CommandStatus::Acknowledged), 32_000)
+                .unwrap_err(),
+            ReportError::NotPending
+        );
+    }
+}
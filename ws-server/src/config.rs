@@ -1,2 +1,64 @@
 pub const SERVER_ADDRESS: &str = "0.0.0.0:3000";
 pub const BROADCAST_CHANNEL_CAPACITY: usize = 100;
+/// Messages buffered per WebSocket connection before its overflow policy applies.
+pub const CLIENT_QUEUE_CAPACITY: usize = 64;
+/// Overrides `CLIENT_QUEUE_CAPACITY`.
+pub const CLIENT_QUEUE_CAPACITY_ENV: &str = "WS_CLIENT_QUEUE_CAPACITY";
+/// Default overflow policy: `drop_oldest`, `drop_newest`, `coalesce` or `disconnect`.
+pub const CLIENT_OVERFLOW_ENV: &str = "WS_CLIENT_OVERFLOW";
+/// Window in which events are batched into one frame; 0 sends every event on its own.
+pub const BATCH_WINDOW_MS: u64 = 0;
+/// Overrides `BATCH_WINDOW_MS`.
+pub const BATCH_WINDOW_ENV: &str = "WS_BATCH_WINDOW_MS";
+/// Set to `true` to let clients negotiate compressed frames.
+pub const COMPRESSION_ENV: &str = "WS_COMPRESSION";
+pub const DEFAULT_DEVICE_ID: &str = "default";
+pub const DATABASE_PATH: &str = "ws-server.db";
+pub const COMMAND_TIMEOUT_MS: u64 = 30_000;
+pub const COMMAND_RETENTION_MS: u64 = 60 * 60 * 1000;
+pub const COMMAND_SWEEP_INTERVAL_MS: u64 = 1_000;
+/// Path of an optional Unix domain socket accepting newline-delimited JSON events.
+pub const UNIX_SOCKET_ENV: &str = "WS_UNIX_SOCKET";
+/// Address (e.g. `127.0.0.1:3001`) of an optional UDP listener accepting one event per datagram.
+pub const UDP_ADDRESS_ENV: &str = "WS_UDP_ADDRESS";
+/// Default silence after which a device is reported offline.
+pub const ALERT_INACTIVITY_MS: u64 = 5 * 60 * 1000;
+/// Default battery level (percent) below which a low-battery alert is raised.
+pub const LOW_BATTERY_THRESHOLD: u8 = 20;
+pub const ALERT_SWEEP_INTERVAL_MS: u64 = 5_000;
+/// Overrides `ALERT_INACTIVITY_MS`.
+pub const ALERT_INACTIVITY_ENV: &str = "WS_ALERT_INACTIVITY_MS";
+/// Overrides `LOW_BATTERY_THRESHOLD`.
+pub const LOW_BATTERY_ENV: &str = "WS_ALERT_LOW_BATTERY";
+/// URL that receives every raised or resolved alert as a JSON POST.
+pub const ALERT_WEBHOOK_ENV: &str = "WS_ALERT_WEBHOOK";
+/// NATS URL (e.g. `nats://127.0.0.1:4222`) of an optional backplane shared by several
+/// ws-server instances.
+pub const NATS_URL_ENV: &str = "WS_NATS_URL";
+/// Identifies this instance on the backplane; defaults to a value derived from the process.
+pub const NODE_ID_ENV: &str = "WS_NODE_ID";
+pub const BACKPLANE_SUBJECT: &str = "ws-server.broadcast";
+/// Cookie holding the login session.
+pub const SESSION_COOKIE: &str = "lgrb_session";
+pub const SESSION_TTL_MS: u64 = 12 * 60 * 60 * 1000;
+/// Password of the `admin` account created on first start; a random one is generated and
+/// logged if unset.
+pub const ADMIN_PASSWORD_ENV: &str = "WS_ADMIN_PASSWORD";
+/// Bearer token ble-listener must send to ingest events and exchange commands; ingest is
+/// open if unset.
+pub const INGEST_TOKEN_ENV: &str = "WS_INGEST_TOKEN";
+/// Largest request body the audit middleware buffers to hash.
+pub const AUDIT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+/// Mutating endpoints left out of the audit log: ingested events are already stored in
+/// the `events` table.
+pub const AUDIT_SKIPPED_PATHS: &[&str] = &[INGEST_PATH];
+pub const AUDIT_PAGE_SIZE: usize = 100;
+pub const AUDIT_MAX_PAGE_SIZE: usize = 1000;
+/// Route of HTTP event ingest, which has its own per-device rate limit.
+pub const INGEST_PATH: &str = "/api/button";
+/// Clients tracked per rate limit before idle ones are forgotten.
+pub const RATE_LIMIT_MAX_KEYS: usize = 10_000;
+/// Reloadable settings file; see `settings.rs`.
+pub const CONFIG_PATH: &str = "ws-server.toml";
+/// Overrides `CONFIG_PATH`.
+pub const CONFIG_PATH_ENV: &str = "WS_CONFIG";
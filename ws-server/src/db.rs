@@ -0,0 +1,99 @@
+use rusqlite::Connection;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run,
+/// so new steps must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS devices (
+        address    TEXT PRIMARY KEY,
+        alias      TEXT,
+        owner      TEXT,
+        location   TEXT,
+        notes      TEXT,
+        first_seen INTEGER NOT NULL,
+        last_seen  INTEGER NOT NULL
+    );",
+    "ALTER TABLE devices ADD COLUMN room TEXT;
+     CREATE TABLE rooms (
+        name     TEXT PRIMARY KEY,
+        capacity INTEGER NOT NULL,
+        token    TEXT
+    );",
+    "CREATE TABLE events (
+        id        INTEGER PRIMARY KEY AUTOINCREMENT,
+        device    TEXT NOT NULL,
+        button    TEXT NOT NULL,
+        state     TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        battery   INTEGER,
+        alias     TEXT
+    );
+     CREATE INDEX events_timestamp ON events (timestamp);",
+    "CREATE TABLE alerts (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        device      TEXT NOT NULL,
+        kind        TEXT NOT NULL,
+        message     TEXT NOT NULL,
+        raised_at   INTEGER NOT NULL,
+        resolved_at INTEGER
+    );",
+    "CREATE TABLE users (
+        username      TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL,
+        role          TEXT NOT NULL,
+        created_at    INTEGER NOT NULL
+    );
+     CREATE TABLE sessions (
+        token      TEXT PRIMARY KEY,
+        username   TEXT NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+     CREATE TABLE webhooks (
+        id         INTEGER PRIMARY KEY AUTOINCREMENT,
+        url        TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE audit (
+        id           INTEGER PRIMARY KEY,
+        at           INTEGER NOT NULL,
+        actor        TEXT,
+        ip           TEXT,
+        method       TEXT NOT NULL,
+        path         TEXT NOT NULL,
+        status       INTEGER NOT NULL,
+        payload_hash TEXT,
+        prev_hash    TEXT NOT NULL,
+        hash         TEXT NOT NULL
+    );
+     CREATE INDEX audit_at ON audit (at);",
+    "ALTER TABLE events ADD COLUMN duration_ms INTEGER;",
+];
+
+/// Shared handle to the server's SQLite database.
+#[derive(Clone)]
+pub struct Db {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Db {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    fn init(mut conn: Connection) -> rusqlite::Result<Self> {
+        let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+        }
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use utoipa::ToSchema;
+
+use crate::event::{ButtonEvent, BATTERY_BUTTON, CONNECTION_BUTTON};
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ButtonState {
+    pub pressed: bool,
+    pub changed_at: u64,
+}
+
+/// Current state of a single device, folded from the events it has sent.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeviceState {
+    pub id: String,
+    pub alias: Option<String>,
+    pub buttons: BTreeMap<String, ButtonState>,
+    pub last_event_at: u64,
+    pub connected: bool,
+    pub battery: Option<u8>,
+}
+
+impl DeviceState {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            alias: None,
+            buttons: BTreeMap::new(),
+            last_event_at: 0,
+            connected: false,
+            battery: None,
+        }
+    }
+
+    pub fn apply(&mut self, event: &ButtonEvent) {
+        self.last_event_at = self.last_event_at.max(event.timestamp);
+        self.connected = true;
+        self.alias = event.alias.clone();
+
+        if let Some(level) = event.battery {
+            self.battery = Some(level);
+        }
+
+        let state = event.state.to_ascii_uppercase();
+        match event.button.to_ascii_uppercase().as_str() {
+            CONNECTION_BUTTON => self.connected = state != "DISCONNECTED",
+            BATTERY_BUTTON => {}
+            "ANY" => {
+                if state == "RELEASED" {
+                    for button in self.buttons.values_mut() {
+                        button.pressed = false;
+                        button.changed_at = event.timestamp;
+                    }
+                }
+            }
+            button => {
+                let pressed = match state.as_str() {
+                    "PRESSED" => true,
+                    "RELEASED" => false,
+                    _ => return,
+                };
+                self.buttons.insert(
+                    button.to_string(),
+                    ButtonState {
+                        pressed,
+                        changed_at: event.timestamp,
+                    },
+                );
+            }
+        }
+    }
+}
@@ -1,8 +1,98 @@
 use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+use crate::alerts::Alert;
+use crate::commands::{Command, CommandKind};
+use crate::device::DeviceState;
+
+/// Button name used by listeners to report connection changes (`CONNECTED` / `DISCONNECTED`).
+pub const CONNECTION_BUTTON: &str = "CONNECTION";
+/// Button name used by listeners to report a battery reading in the `battery` field.
+pub const BATTERY_BUTTON: &str = "BATTERY";
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ButtonEvent {
+    /// `A`, `B`, `ANY`, or one of the reserved `CONNECTION` / `BATTERY` names.
     pub button: String,
+    /// `PRESSED` / `RELEASED`, or `CONNECTED` / `DISCONNECTED` for `CONNECTION`.
     pub state: String,
+    /// Unix time in milliseconds.
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<u8>,
+    /// For releases, how long the button was held, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Trace>,
+}
+
+impl ButtonEvent {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.button.trim().is_empty() {
+            return Err("button must not be empty".to_string());
+        }
+        if self.state.trim().is_empty() {
+            return Err("state must not be empty".to_string());
+        }
+        if matches!(self.battery, Some(level) if level > 100) {
+            return Err("battery must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Timestamps stamped by each hop an event passes through. Host times are microseconds
+/// since the Unix epoch; `device_tick` is the device's own uptime in milliseconds.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct Trace {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_tick: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener_received_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener_sent_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_ingested_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ws_sent_at: Option<u64>,
+}
+
+/// Messages pushed to WebSocket clients.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Event(ButtonEvent),
+    Snapshot { devices: Vec<DeviceState> },
+    Command(Command),
+    Alert(Alert),
+}
+
+/// Messages accepted from WebSocket clients.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Command {
+        device: String,
+        command: CommandKind,
+    },
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default()
 }
@@ -0,0 +1,319 @@
+use arrow_array::{
+    ArrayRef, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt64Array,
+    UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use futures_util::stream::{self, Stream};
+use parquet::arrow::ArrowWriter;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::history::{EventFilter, EventHistory, StoredEvent};
+
+/// Number of events read from the database per chunk (and per Parquet row group).
+const PAGE_SIZE: usize = 1000;
+
+pub type ExportError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    Ndjson,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+/// `Write` target whose contents can be taken while the Parquet writer still owns it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn parquet_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("device", DataType::Utf8, false),
+        Field::new("button", DataType::Utf8, false),
+        Field::new("state", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("battery", DataType::UInt8, true),
+        Field::new("alias", DataType::Utf8, true),
+        Field::new("duration_ms", DataType::UInt64, true),
+    ]))
+}
+
+fn record_batch(schema: &SchemaRef, events: &[StoredEvent]) -> Result<RecordBatch, ExportError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int64Array::from_iter_values(events.iter().map(|e| e.id))),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| &e.device),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| &e.button),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            events.iter().map(|e| &e.state),
+        )),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(events.iter().map(|e| e.timestamp as i64))
+                .with_timezone("UTC"),
+        ),
+        Arc::new(UInt8Array::from_iter(events.iter().map(|e| e.battery))),
+        Arc::new(StringArray::from_iter(
+            events.iter().map(|e| e.alias.as_deref()),
+        )),
+        Arc::new(UInt64Array::from_iter(events.iter().map(|e| e.duration_ms))),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Incrementally encodes pages of events into the bytes of one export file.
+enum Encoder {
+    Csv {
+        header_written: bool,
+    },
+    Ndjson,
+    Parquet {
+        schema: SchemaRef,
+        writer: Box<ArrowWriter<SharedBuffer>>,
+        buffer: SharedBuffer,
+    },
+}
+
+impl Encoder {
+    fn new(format: ExportFormat) -> Result<Self, ExportError> {
+        Ok(match format {
+            ExportFormat::Csv => Self::Csv {
+                header_written: false,
+            },
+            ExportFormat::Ndjson => Self::Ndjson,
+            ExportFormat::Parquet => {
+                let schema = parquet_schema();
+                let buffer = SharedBuffer::default();
+                let writer = Box::new(ArrowWriter::try_new(buffer.clone(), schema.clone(), None)?);
+                Self::Parquet {
+                    schema,
+                    writer,
+                    buffer,
+                }
+            }
+        })
+    }
+
+    fn encode(&mut self, events: &[StoredEvent]) -> Result<Vec<u8>, ExportError> {
+        match self {
+            Self::Csv { header_written } => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(!*header_written)
+                    .from_writer(Vec::new());
+                for event in events {
+                    writer.serialize(event)?;
+                }
+                *header_written = true;
+                Ok(writer.into_inner().map_err(|e| e.into_error())?)
+            }
+            Self::Ndjson => {
+                let mut out = Vec::new();
+                for event in events {
+                    serde_json::to_writer(&mut out, event)?;
+                    out.push(b'\n');
+                }
+                Ok(out)
+            }
+            Self::Parquet {
+                schema,
+                writer,
+                buffer,
+            } => {
+                writer.write(&record_batch(schema, events)?)?;
+                // Closing the row group pushes its bytes into the buffer.
+                writer.flush()?;
+                Ok(buffer.take())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<Vec<u8>, ExportError> {
+        match self {
+            Self::Csv {
+                header_written: false,
+            } => Ok(b"id,device,button,state,timestamp,battery,alias,duration_ms\n".to_vec()),
+            Self::Csv { .. } | Self::Ndjson => Ok(Vec::new()),
+            Self::Parquet { writer, buffer, .. } => {
+                writer.close()?;
+                Ok(buffer.take())
+            }
+        }
+    }
+}
+
+/// Streams the matching events in `format`, reading them from the database one page at a
+/// time so large exports never sit in memory.
+pub fn stream_events(
+    history: EventHistory,
+    filter: EventFilter,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Vec<u8>, ExportError>> {
+    // The state is the encoder and the last exported id; `None` ends the stream.
+    let start = Encoder::new(format).map(|encoder| (encoder, 0));
+    stream::unfold(Some(start), move |state| {
+        let history = history.clone();
+        let filter = filter.clone();
+        async move {
+            let (mut encoder, after) = match state? {
+                Ok(state) => state,
+                Err(e) => return Some((Err(e), None)),
+            };
+            let events = match history.page(&filter, after, PAGE_SIZE) {
+                Ok(events) => events,
+                Err(e) => return Some((Err(e.into()), None)),
+            };
+            let Some(last) = events.last().map(|e| e.id) else {
+                return Some((encoder.finish(), None));
+            };
+            let chunk = encoder.encode(&events);
+            Some((chunk, Some(Ok((encoder, last)))))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::event::ButtonEvent;
+    use axum::body::Bytes;
+    use futures_util::StreamExt;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const EVENTS: usize = PAGE_SIZE + 5;
+
+    fn history() -> EventHistory {
+        let history = EventHistory::new(Db::open(":memory:").unwrap());
+        for i in 0..EVENTS as u64 {
+            let event = ButtonEvent {
+                button: "A".to_string(),
+                state: "RELEASED".to_string(),
+                timestamp: 1_000 + i,
+                device: None,
+                battery: None,
+                duration_ms: i.is_multiple_of(2).then_some(i),
+                alias: None,
+                trace: None,
+            };
+            history.append("AA", &event).unwrap();
+        }
+        history
+    }
+
+    async fn export(format: ExportFormat) -> Vec<u8> {
+        let chunks: Vec<_> = stream_events(history(), EventFilter::default(), format)
+            .collect()
+            .await;
+        chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.unwrap())
+            .collect()
+    }
+
+    fn expected_duration(row: usize) -> Option<u64> {
+        row.is_multiple_of(2).then_some(row as u64)
+    }
+
+    #[tokio::test]
+    async fn exports_every_page_with_durations() {
+        let csv = export(ExportFormat::Csv).await;
+        let mut reader = csv::Reader::from_reader(csv.as_slice());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "id",
+                "device",
+                "button",
+                "state",
+                "timestamp",
+                "battery",
+                "alias",
+                "duration_ms"
+            ]
+        );
+        let rows: Vec<_> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), EVENTS);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(row[0].parse::<usize>().unwrap(), i + 1);
+            assert_eq!(row[7].parse().ok(), expected_duration(i));
+        }
+
+        let ndjson = export(ExportFormat::Ndjson).await;
+        let lines: Vec<serde_json::Value> = ndjson
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), EVENTS);
+        for (i, line) in lines.iter().enumerate() {
+            assert_eq!(line["id"], i as u64 + 1);
+            assert_eq!(line["duration_ms"].as_u64(), expected_duration(i));
+        }
+
+        let parquet = export(ExportFormat::Parquet).await;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(parquet))
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut durations = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = batch.column_by_name("duration_ms").unwrap();
+            let column = column.as_any().downcast_ref::<UInt64Array>().unwrap();
+            durations.extend(column.iter());
+        }
+        assert_eq!(
+            durations,
+            (0..EVENTS).map(expected_duration).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config::BATCH_WINDOW_MS;
+use crate::event::ServerMessage;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameSettings {
+    /// Whether clients may negotiate the `permessage-deflate` extension.
+    pub compression: bool,
+    /// Events arriving within this window are sent as one JSON array; 0 disables batching.
+    pub batch_window_ms: u64,
+}
+
+impl Default for FrameSettings {
+    fn default() -> Self {
+        Self {
+            compression: false,
+            batch_window_ms: BATCH_WINDOW_MS,
+        }
+    }
+}
+
+/// Only events are batched; anything else is sent as soon as it is dequeued.
+pub fn is_batchable(message: &ServerMessage) -> bool {
+    matches!(message, ServerMessage::Event(_))
+}
+
+/// Serializes a single message, or a JSON array if several were batched, into the text of
+/// one WebSocket message.
+pub fn encode(batch: &[ServerMessage]) -> Result<String, String> {
+    match batch {
+        [message] => serde_json::to_string(message),
+        messages => serde_json::to_string(messages),
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ButtonEvent;
+
+    #[test]
+    fn batches_are_sent_as_json_arrays() {
+        let event = ServerMessage::Event(ButtonEvent {
+            button: "A".to_string(),
+            state: "PRESSED".to_string(),
+            timestamp: 1,
+            device: None,
+            battery: None,
+            duration_ms: None,
+            alias: None,
+            trace: None,
+        });
+        let single: serde_json::Value =
+            serde_json::from_str(&encode(std::slice::from_ref(&event)).unwrap()).unwrap();
+        assert_eq!(single["type"], "event");
+        let batch: serde_json::Value =
+            serde_json::from_str(&encode(&[event.clone(), event]).unwrap()).unwrap();
+        assert_eq!(batch.as_array().unwrap().len(), 2);
+    }
+}
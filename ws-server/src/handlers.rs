@@ -1,82 +1,1239 @@
 use axum::{
-    extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
-    },
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    body::Body,
+    extract::{ws::WebSocketUpgrade, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect},
+    Extension, Form, Json,
 };
-use axum::extract::ws::Utf8Bytes;
-use futures_util::{sink::SinkExt, stream::StreamExt};
+use futures_util::{
+    sink::{Sink, SinkExt},
+    stream::{Stream, StreamExt},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::event::ButtonEvent;
+use crate::alerts::Alert;
+use crate::audit::{
+    sha256_hex, AuditActor, AuditEntry, AuditFilter, AuditRecord, AuditVerification, ClientIp,
+};
+use crate::auth::{
+    current_user, session_token, Admin, Auth, IngestAuth, NewUser, Operator, Role, User, UserPatch,
+    Viewer,
+};
+use crate::commands::{Command, CommandKind, CommandReport, ReportError};
+use crate::config::{AUDIT_MAX_PAGE_SIZE, AUDIT_PAGE_SIZE, SESSION_COOKIE, SESSION_TTL_MS};
+use crate::device::DeviceState;
+use crate::event::{now_micros, now_millis, ButtonEvent, ClientMessage, ServerMessage};
+use crate::export::{stream_events, ExportFormat};
+use crate::framing::{self, is_batchable};
+use crate::history::EventFilter;
+use crate::ingest::{ingest_event, serve_stream, within_rate_limit};
+use crate::latency::LatencyReport;
+use crate::logging::{error, info, warn};
+use crate::outbound::{ClientQueue, OverflowPolicy, QueueSettings};
+use crate::registry::{Device, DevicePatch, NewDevice};
+use crate::rooms::{validate_name, JoinError, NewRoom, Room, DEFAULT_ROOM, MAX_ROOM_CAPACITY};
+use crate::settings::{self, Settings};
 use crate::state::AppState;
+use crate::webhook::{NewWebhook, WebhookTarget};
+use crate::websocket::ClientUpgrade;
 
+/// Upgrade to a WebSocket that streams the default room's `ServerMessage`s and accepts
+/// `ClientMessage`s.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "websocket",
+    params(JoinQuery),
+    security(("session" = [])),
+    responses((status = 101, description = "Switching protocols to WebSocket"))
+)]
 pub async fn websocket_handler(
-    ws: WebSocketUpgrade,
+    Auth(user, _): Auth<Viewer>,
+    ClientIp(ip): ClientIp,
+    ws: ClientUpgrade,
     State(state): State<AppState>,
+    Query(query): Query<JoinQuery>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+    join_room(ws, state, DEFAULT_ROOM.to_string(), query, user, ip)
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct JoinQuery {
+    /// Access token of a protected room.
+    token: Option<String>,
+    /// What to do when this client falls behind; defaults to the server setting.
+    #[param(inline)]
+    overflow: Option<OverflowPolicy>,
+    /// Event batching window in milliseconds (0 disables); defaults to the server setting.
+    batch_ms: Option<u64>,
+}
+
+/// How messages are delivered to one connection.
+struct ClientSettings {
+    queue: QueueSettings,
+    batch_window: Duration,
+    /// The logged-in user; commands require [`Role::Operator`].
+    user: User,
+    /// Peer address, recorded in the audit log with every command.
+    ip: Option<String>,
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
-    let (mut sender, mut receiver) = socket.split();
-    let mut button_rx = state.button_tx.subscribe();
+/// Upgrade to a WebSocket that streams the messages of a single room.
+#[utoipa::path(
+    get,
+    path = "/ws/{room}",
+    tag = "websocket",
+    params(("room" = String, Path, description = "Room name"), JoinQuery),
+    security(("session" = [])),
+    responses(
+        (status = 101, description = "Switching protocols to WebSocket"),
+        (status = 401, description = "Missing or wrong room token", body = String),
+        (status = 404, description = "Room not found", body = String)
+    )
+)]
+pub async fn websocket_room_handler(
+    Auth(user, _): Auth<Viewer>,
+    ClientIp(ip): ClientIp,
+    ws: ClientUpgrade,
+    State(state): State<AppState>,
+    Path(room): Path<String>,
+    Query(query): Query<JoinQuery>,
+) -> impl IntoResponse {
+    join_room(ws, state, room, query, user, ip)
+}
 
-    let send_task = tokio::spawn(async move {
-        while let Ok(event) = button_rx.recv().await {
-            match serde_json::to_string(&event) {
-                Ok(msg) => {
-                    if sender.send(Message::Text(Utf8Bytes::from(msg))).await.is_err() {
+fn join_room(
+    ws: ClientUpgrade,
+    state: AppState,
+    room: String,
+    query: JoinQuery,
+    user: User,
+    ip: Option<String>,
+) -> axum::response::Response {
+    let settings = ClientSettings {
+        queue: QueueSettings {
+            overflow: query.overflow.unwrap_or(state.client_queue.overflow),
+            ..state.client_queue
+        },
+        batch_window: Duration::from_millis(
+            query.batch_ms.unwrap_or(state.framing.batch_window_ms),
+        ),
+        user,
+        ip,
+    };
+    match state.rooms.join(&room, query.token.as_deref()) {
+        Ok(message_rx) => ws.on_upgrade(state.framing.compression, move |sender, receiver| {
+            handle_socket(sender, receiver, state, room, message_rx, settings)
+        }),
+        Err(JoinError::NotFound) => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+        Err(JoinError::Unauthorized) => {
+            (StatusCode::UNAUTHORIZED, "Invalid room token").into_response()
+        }
+    }
+}
+
+/// Serves one client of `room` over the two halves of its WebSocket, carrying text
+/// messages, until either side ends, then stops every task serving it.
+async fn handle_socket<W, R>(
+    mut sender: W,
+    mut receiver: R,
+    state: AppState,
+    room: String,
+    mut message_rx: broadcast::Receiver<ServerMessage>,
+    settings: ClientSettings,
+) where
+    W: Sink<String> + Unpin + Send + 'static,
+    R: Stream<Item = String> + Unpin + Send + 'static,
+{
+    let snapshot = ServerMessage::Snapshot {
+        devices: state.device_snapshot(&room).await,
+    };
+    match framing::encode(&[snapshot]) {
+        Ok(frame) => {
+            if sender.send(frame).await.is_err() {
+                return;
+            }
+        }
+        Err(e) => {
+            error!("Failed to serialize snapshot: {}", e);
+        }
+    }
+
+    // The room's channel feeds this client's own queue, so a slow client never makes the
+    // channel lag and only its own overflow policy decides what it loses.
+    let queue = Arc::new(ClientQueue::new(settings.queue));
+    let forward_queue = queue.clone();
+    let forward_task = tokio::spawn(async move {
+        loop {
+            match message_rx.recv().await {
+                Ok(message) => {
+                    if !forward_queue.push(message) {
+                        info!("Disconnecting client whose outbound queue overflowed");
                         break;
                     }
                 }
-                Err(e) => {
-                    eprintln!("Failed to serialize event: {}", e);
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Client forwarder lagged, skipped {} messages", skipped);
                 }
+                Err(broadcast::error::RecvError::Closed) => break,
             }
         }
+        forward_queue.close();
     });
 
-    let recv_task = tokio::spawn(async move {
-        while let Some(result) = receiver.next().await {
-            match result {
-                Ok(msg) => match msg {
-                    Message::Close(_) => break,
-                    _ => {}
-                },
+    let latency = state.latency.clone();
+    let send_queue = queue.clone();
+    let batch_window = settings.batch_window;
+    let mut send_task = tokio::spawn(async move {
+        while let Some(message) = send_queue.pop().await {
+            let mut batch = vec![message];
+            if !batch_window.is_zero() && is_batchable(&batch[0]) {
+                let deadline = tokio::time::Instant::now() + batch_window;
+                while let Ok(Some(next)) = tokio::time::timeout_at(deadline, send_queue.pop()).await
+                {
+                    let batchable = is_batchable(&next);
+                    batch.push(next);
+                    if !batchable {
+                        break;
+                    }
+                }
+            }
+
+            let sent_at = now_micros();
+            for message in &mut batch {
+                if let ServerMessage::Event(ButtonEvent {
+                    trace: Some(trace), ..
+                }) = message
+                {
+                    trace.ws_sent_at = Some(sent_at);
+                }
+            }
+            match framing::encode(&batch) {
+                Ok(frame) => {
+                    if sender.send(frame).await.is_err() {
+                        break;
+                    }
+                    for message in &batch {
+                        if let ServerMessage::Event(ButtonEvent {
+                            trace: Some(trace), ..
+                        }) = message
+                        {
+                            latency.record_ws_send(trace);
+                        }
+                    }
+                }
                 Err(e) => {
-                    eprintln!("WebSocket receive error: {}", e);
-                    break;
+                    error!("Failed to serialize message: {}", e);
                 }
             }
         }
     });
 
+    let client = Client {
+        user: settings.user,
+        ip: settings.ip,
+        path: format!("/ws/{}", room),
+    };
+    let mut recv_task = tokio::spawn(async move {
+        while let Some(text) = receiver.next().await {
+            handle_client_message(&state, &client, &text);
+        }
+    });
+
     tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
+        _ = &mut send_task => {},
+        _ = &mut recv_task => {},
+    }
+    // Aborting the forwarder skips its `close`, so close the queue here to end a sender
+    // still waiting on it.
+    queue.close();
+    forward_task.abort();
+    send_task.abort();
+    recv_task.abort();
+
+    let dropped = queue.dropped();
+    if dropped > 0 {
+        info!(
+            "Client dropped {} messages due to a full outbound queue",
+            dropped
+        );
+    }
+}
+
+/// Who is on the other end of a WebSocket, for authorization and the audit log.
+struct Client {
+    user: User,
+    ip: Option<String>,
+    path: String,
+}
+
+fn handle_client_message(state: &AppState, client: &Client, text: &str) {
+    match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Command { device, command }) => {
+            let status = if client.user.role < Role::Operator {
+                warn!("Ignoring command from a client without the operator role");
+                StatusCode::FORBIDDEN
+            } else if let Err(e) = command.validate() {
+                warn!("Ignoring invalid command for {}: {}", device, e);
+                StatusCode::UNPROCESSABLE_ENTITY
+            } else {
+                queue_command(state, &device, command);
+                StatusCode::ACCEPTED
+            };
+            state.audit.record(AuditRecord {
+                at: now_millis(),
+                actor: Some(client.user.username.clone()),
+                ip: client.ip.clone(),
+                method: "WS".to_string(),
+                path: client.path.clone(),
+                status: status.as_u16(),
+                payload_hash: Some(sha256_hex(text.as_bytes())),
+            });
+        }
+        Err(e) => {
+            warn!("Ignoring invalid client message: {}", e);
+        }
     }
 }
 
-pub async fn serve_html() -> impl IntoResponse {
-    Html(include_str!("../index.html"))
+fn queue_command(state: &AppState, device: &str, command: CommandKind) -> Command {
+    let command = state.commands.enqueue(device, command, now_millis());
+    info!("Queued command {} for {}", command.id, device);
+    state.broadcast_for_device(device, ServerMessage::Command(command.clone()));
+    command
+}
+
+/// The dashboard, for logged-in users; everyone else is sent to the login page.
+pub async fn serve_html(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    match current_user(&state, &headers) {
+        Some(_) => Html(include_str!("../index.html")).into_response(),
+        None => Redirect::to("/login").into_response(),
+    }
 }
 
+/// Ingest a button event and broadcast it to every WebSocket client.
+#[utoipa::path(
+    post,
+    path = "/api/button",
+    tag = "events",
+    security(("ingest_token" = []), ("session" = [])),
+    request_body = ButtonEvent,
+    responses(
+        (status = 200, description = "Event received", body = String),
+        (status = 422, description = "Invalid event", body = String),
+        (status = 429, description = "Device exceeded its event rate limit", body = String)
+    )
+)]
 pub async fn button_event(
+    _: IngestAuth,
+    State(state): State<AppState>,
+    Json(event): Json<ButtonEvent>,
+) -> impl IntoResponse {
+    if let Err(e) = event.validate() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    if !within_rate_limit(&state, &event) {
+        return (StatusCode::TOO_MANY_REQUESTS, "Event rate limit exceeded").into_response();
+    }
+
+    ingest_event(&state, event).await;
+
+    (StatusCode::OK, "Event received").into_response()
+}
+
+/// Upgrade to a WebSocket on which a listener streams events as `{"seq", "event"}` frames.
+/// Frames are ingested in order and each is answered with `{"seq", "status", "error"}`,
+/// `status` being `accepted` or `rejected`.
+#[utoipa::path(
+    get,
+    path = "/api/button/stream",
+    tag = "events",
+    security(("ingest_token" = []), ("session" = [])),
+    responses(
+        (status = 101, description = "Switching protocols to WebSocket"),
+        (status = 401, description = "Missing or wrong ingest token", body = String)
+    )
+)]
+pub async fn button_stream(
+    _: IngestAuth,
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| serve_stream(socket, state))
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ExportQuery {
+    /// Output format; defaults to `ndjson`.
+    #[serde(default)]
+    #[param(inline)]
+    format: ExportFormat,
+    /// Earliest event timestamp (Unix ms), inclusive.
+    from: Option<u64>,
+    /// Latest event timestamp (Unix ms), inclusive.
+    to: Option<u64>,
+    /// Only events of this device.
+    device: Option<String>,
+}
+
+/// Download stored events as CSV, NDJSON or Parquet.
+#[utoipa::path(
+    get,
+    path = "/api/events/export",
+    tag = "events",
+    security(("session" = [])),
+    params(ExportQuery),
+    responses((
+        status = 200,
+        description = "Stored events in the requested format, oldest first",
+        content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+            (Vec<u8> = "application/vnd.apache.parquet")
+        )
+    ))
+)]
+pub async fn export_events(
+    _: Auth<Viewer>,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let filter = EventFilter {
+        from: query.from,
+        to: query.to,
+        device: query.device,
+    };
+    let format = query.format;
+    let filename = format!("events-{}.{}", now_millis(), format.extension());
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(stream_events(state.history.clone(), filter, format)),
+    )
+}
+
+/// Current state of a device, folded from its events.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/state",
+    tag = "devices",
+    security(("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    responses(
+        (status = 200, body = DeviceState),
+        (status = 404, description = "Device has not sent any events", body = String)
+    )
+)]
+pub async fn device_state(
+    _: Auth<Viewer>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.devices.read().await.get(&id) {
+        Some(device) => Json(device.clone()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+    }
+}
+
+/// Rejects assignments to rooms that do not exist; an empty name means the default room.
+fn check_room(state: &AppState, room: Option<&str>) -> Result<(), (StatusCode, &'static str)> {
+    match room {
+        Some(room) if !room.is_empty() && !state.rooms.exists(room) => {
+            Err((StatusCode::UNPROCESSABLE_ENTITY, "Room not found"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Runs `f` on the blocking thread pool. Used for user operations that hash or verify a
+/// password, since Argon2 would hold up every other task on the runtime thread.
+async fn run_blocking<T, F>(f: F) -> rusqlite::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> rusqlite::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?
+}
+
+fn internal_error(e: rusqlite::Error) -> (StatusCode, String) {
+    error!("Database error: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Database error".to_string(),
+    )
+}
+
+/// List every registered device.
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    tag = "devices",
+    security(("session" = [])),
+    responses((status = 200, body = Vec<Device>))
+)]
+pub async fn list_devices(_: Auth<Viewer>, State(state): State<AppState>) -> impl IntoResponse {
+    state.registry.list().map(Json).map_err(internal_error)
+}
+
+/// Get a registered device.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    security(("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    responses(
+        (status = 200, body = Device),
+        (status = 404, description = "Device not found", body = String)
+    )
+)]
+pub async fn get_device(
+    _: Auth<Viewer>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.registry.get(&id) {
+        Ok(Some(device)) => Json(device).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Register a device.
+#[utoipa::path(
+    post,
+    path = "/api/devices",
+    tag = "devices",
+    security(("session" = [])),
+    request_body = NewDevice,
+    responses(
+        (status = 201, body = Device),
+        (status = 409, description = "Device already registered", body = String),
+        (status = 422, description = "Room not found", body = String)
+    )
+)]
+pub async fn create_device(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Json(device): Json<NewDevice>,
+) -> impl IntoResponse {
+    if let Err(response) = check_room(&state, device.room.as_deref()) {
+        return response.into_response();
+    }
+    match state.registry.create(device, now_millis()) {
+        Ok(Some(device)) => (StatusCode::CREATED, Json(device)).into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "Device already registered").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Update a registered device; omitted fields are left unchanged.
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    security(("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    request_body = DevicePatch,
+    responses(
+        (status = 200, body = Device),
+        (status = 404, description = "Device not found", body = String),
+        (status = 422, description = "Room not found", body = String)
+    )
+)]
+pub async fn update_device(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(patch): Json<DevicePatch>,
+) -> impl IntoResponse {
+    if let Err(response) = check_room(&state, patch.room.as_deref()) {
+        return response.into_response();
+    }
+    match state.registry.update(&id, patch) {
+        Ok(Some(device)) => Json(device).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Remove a device from the registry.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    security(("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    responses(
+        (status = 204, description = "Device removed"),
+        (status = 404, description = "Device not found", body = String)
+    )
+)]
+pub async fn delete_device(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match state.registry.delete(&id) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Device not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// List rooms, including the default room.
+#[utoipa::path(
+    get,
+    path = "/api/rooms",
+    tag = "rooms",
+    security(("session" = [])),
+    responses((status = 200, body = Vec<Room>))
+)]
+pub async fn list_rooms(_: Auth<Viewer>, State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.rooms.list())
+}
+
+/// Create a room with its own broadcast channel.
+#[utoipa::path(
+    post,
+    path = "/api/rooms",
+    tag = "rooms",
+    security(("session" = [])),
+    request_body = NewRoom,
+    responses(
+        (status = 201, body = Room),
+        (status = 409, description = "Room already exists", body = String),
+        (status = 422, description = "Invalid room name or capacity", body = String)
+    )
+)]
+pub async fn create_room(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Json(room): Json<NewRoom>,
+) -> impl IntoResponse {
+    if let Err(e) = validate_name(&room.name) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    if room
+        .capacity
+        .is_some_and(|capacity| capacity > MAX_ROOM_CAPACITY)
+    {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Room capacity is larger than 10000",
+        )
+            .into_response();
+    }
+    match state.rooms.create(room) {
+        Ok(Some(room)) => (StatusCode::CREATED, Json(room)).into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "Room already exists").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Delete a room, disconnecting its clients; its devices move to the default room.
+#[utoipa::path(
+    delete,
+    path = "/api/rooms/{name}",
+    tag = "rooms",
+    security(("session" = [])),
+    params(("name" = String, Path, description = "Room name")),
+    responses(
+        (status = 204, description = "Room deleted"),
+        (status = 404, description = "Room not found", body = String)
+    )
+)]
+pub async fn delete_room(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.rooms.delete(&name) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Queue a downlink command for a device.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/commands",
+    tag = "commands",
+    security(("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    request_body = CommandKind,
+    responses(
+        (status = 202, body = Command),
+        (status = 422, description = "Arguments the device would reject, such as a `show_text` text over 19 bytes", body = String)
+    )
+)]
+pub async fn create_command(
+    _: Auth<Operator>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(command): Json<CommandKind>,
+) -> impl IntoResponse {
+    if let Err(e) = command.validate() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, e).into_response();
+    }
+    let command = queue_command(&state, &id, command);
+    (StatusCode::ACCEPTED, Json(command)).into_response()
+}
+
+/// List recent commands for a device.
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/commands",
+    tag = "commands",
+    security(("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    responses((status = 200, body = Vec<Command>))
+)]
+pub async fn list_commands(
+    _: Auth<Viewer>,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    Json(state.commands.for_device(&id))
+}
+
+/// Take the queued commands for a device, marking them delivered.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/commands/pull",
+    tag = "commands",
+    security(("ingest_token" = []), ("session" = [])),
+    params(("id" = String, Path, description = "Device BLE address")),
+    responses((status = 200, body = Vec<Command>))
+)]
+pub async fn pull_commands(
+    _: IngestAuth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let commands = state.commands.take_pending(&id, now_millis());
+    for command in &commands {
+        state.broadcast_for_device(&id, ServerMessage::Command(command.clone()));
+    }
+    Json(commands)
+}
+
+/// Report the outcome of a delivered command.
+#[utoipa::path(
+    post,
+    path = "/api/commands/{id}/status",
+    tag = "commands",
+    security(("ingest_token" = []), ("session" = [])),
+    params(("id" = u64, Path, description = "Command id")),
+    request_body = CommandReport,
+    responses(
+        (status = 200, body = Command),
+        (status = 404, description = "No pending command with that id", body = String),
+        (status = 409, description = "The report would move the command back", body = String)
+    )
+)]
+pub async fn report_command(
+    _: IngestAuth,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(report): Json<CommandReport>,
+) -> impl IntoResponse {
+    match state.commands.report(id, report, now_millis()) {
+        Ok(command) => {
+            state.broadcast_for_device(&command.device, ServerMessage::Command(command.clone()));
+            Json(command).into_response()
+        }
+        Err(ReportError::NotPending) => {
+            (StatusCode::NOT_FOUND, "No pending command with that id").into_response()
+        }
+        Err(ReportError::Backwards) => (
+            StatusCode::CONFLICT,
+            "A command cannot move back to an earlier status",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct AlertQuery {
+    /// Only return unresolved alerts.
+    #[serde(default)]
+    active: bool,
+}
+
+/// Alert log, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/alerts",
+    tag = "alerts",
+    security(("session" = [])),
+    params(AlertQuery),
+    responses((status = 200, body = Vec<Alert>))
+)]
+pub async fn list_alerts(
+    _: Auth<Viewer>,
+    State(state): State<AppState>,
+    Query(query): Query<AlertQuery>,
+) -> impl IntoResponse {
+    state
+        .alerts
+        .list(query.active)
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Per-hop latency histograms.
+#[utoipa::path(
+    get,
+    path = "/api/latency",
+    tag = "metrics",
+    security(("session" = [])),
+    responses((status = 200, body = LatencyReport))
+)]
+pub async fn latency_report(_: Auth<Viewer>, State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.latency.report())
+}
+
+/// Latency histograms in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "metrics",
+    responses((status = 200, content_type = "text/plain", body = String))
+)]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        state.latency.prometheus(),
+    )
+}
+
+/// Login form served to visitors without a session.
+#[utoipa::path(
+    get,
+    path = "/login",
+    tag = "auth",
+    responses((status = 200, content_type = "text/html", body = String))
+)]
+pub async fn login_page() -> impl IntoResponse {
+    Html(include_str!("../login.html"))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+fn session_cookie(token: &str, max_age_ms: u64) -> String {
+    format!(
+        "{}={}; HttpOnly; SameSite=Strict; Path=/; Max-Age={}",
+        SESSION_COOKIE,
+        token,
+        max_age_ms / 1000
+    )
+}
+
+/// Start a session; redirects to the dashboard, or back to the form if the credentials
+/// are wrong.
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body(content = LoginForm, content_type = "application/x-www-form-urlencoded"),
+    responses((
+        status = 303,
+        description = "Redirect to `/` with the session cookie set, or to `/login?error=1`"
+    ))
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    Form(form): Form<LoginForm>,
+) -> impl IntoResponse {
+    let actor = AuditActor(form.username.clone());
+    let users = state.users.clone();
+    let (username, password) = (form.username.clone(), form.password);
+    match run_blocking(move || users.login(&username, &password, now_millis())).await {
+        Ok(Some(token)) => {
+            info!("User {} logged in", form.username);
+            (
+                [(header::SET_COOKIE, session_cookie(&token, SESSION_TTL_MS))],
+                Extension(actor),
+                Redirect::to("/"),
+            )
+                .into_response()
+        }
+        Ok(None) => {
+            info!("Failed login for {}", form.username);
+            (Extension(actor), Redirect::to("/login?error=1")).into_response()
+        }
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// End the current session.
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "auth",
+    responses((status = 303, description = "Redirect to `/login` with the session cookie cleared"))
+)]
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(token) = session_token(&headers) {
+        if let Err(e) = state.users.logout(token) {
+            return internal_error(e).into_response();
+        }
+    }
+    (
+        [(header::SET_COOKIE, session_cookie("", 0))],
+        Redirect::to("/login"),
+    )
+        .into_response()
+}
+
+/// The logged-in user.
+#[utoipa::path(
+    get,
+    path = "/api/me",
+    tag = "auth",
+    security(("session" = [])),
+    responses(
+        (status = 200, body = User),
+        (status = 401, description = "Not logged in", body = String)
+    )
+)]
+pub async fn me(Auth(user, _): Auth<Viewer>) -> impl IntoResponse {
+    Json(user)
+}
+
+/// List user accounts.
+#[utoipa::path(
+    get,
+    path = "/api/users",
+    tag = "auth",
+    security(("session" = [])),
+    responses((status = 200, body = Vec<User>))
+)]
+pub async fn list_users(_: Auth<Admin>, State(state): State<AppState>) -> impl IntoResponse {
+    state.users.list().map(Json).map_err(internal_error)
+}
+
+/// Create a user account.
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "auth",
+    security(("session" = [])),
+    request_body = NewUser,
+    responses(
+        (status = 201, body = User),
+        (status = 409, description = "Username taken", body = String),
+        (status = 422, description = "Empty username or password", body = String)
+    )
+)]
+pub async fn create_user(
+    _: Auth<Admin>,
     State(state): State<AppState>,
-    axum::extract::Json(event): axum::extract::Json<ButtonEvent>,
+    Json(user): Json<NewUser>,
 ) -> impl IntoResponse {
-    println!("Received button event: {:?}", event);
+    if user.username.is_empty() || user.password.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Username and password must not be empty",
+        )
+            .into_response();
+    }
+    let users = state.users.clone();
+    match run_blocking(move || users.create(user, now_millis())).await {
+        Ok(Some(user)) => (StatusCode::CREATED, Json(user)).into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "Username taken").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
 
-    match state.button_tx.send(event) {
-        Ok(receiver_count) => {
-            println!("Event broadcasted to {} receivers", receiver_count);
+/// Change a user's password or role; this ends the user's sessions.
+#[utoipa::path(
+    patch,
+    path = "/api/users/{name}",
+    tag = "auth",
+    security(("session" = [])),
+    params(("name" = String, Path, description = "Username")),
+    request_body = UserPatch,
+    responses(
+        (status = 200, body = User),
+        (status = 404, description = "User not found", body = String),
+        (status = 409, description = "Admins cannot change their own role", body = String),
+        (status = 422, description = "Empty password", body = String)
+    )
+)]
+pub async fn update_user(
+    Auth(admin, _): Auth<Admin>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(patch): Json<UserPatch>,
+) -> impl IntoResponse {
+    if patch.password.as_deref() == Some("") {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Password must not be empty",
+        )
+            .into_response();
+    }
+    if name == admin.username && patch.role.is_some_and(|role| role != admin.role) {
+        return (StatusCode::CONFLICT, "Admins cannot change their own role").into_response();
+    }
+    let users = state.users.clone();
+    match run_blocking(move || users.update(&name, patch)).await {
+        Ok(Some(user)) => Json(user).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Delete a user account.
+#[utoipa::path(
+    delete,
+    path = "/api/users/{name}",
+    tag = "auth",
+    security(("session" = [])),
+    params(("name" = String, Path, description = "Username")),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 404, description = "User not found", body = String),
+        (status = 409, description = "Admins cannot delete themselves", body = String)
+    )
+)]
+pub async fn delete_user(
+    Auth(admin, _): Auth<Admin>,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    if name == admin.username {
+        return (StatusCode::CONFLICT, "Admins cannot delete themselves").into_response();
+    }
+    match state.users.delete(&name) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// List the alert webhooks registered through the API.
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "alerts",
+    security(("session" = [])),
+    responses((status = 200, body = Vec<WebhookTarget>))
+)]
+pub async fn list_webhooks(_: Auth<Admin>, State(state): State<AppState>) -> impl IntoResponse {
+    state.webhook.list().map(Json).map_err(internal_error)
+}
+
+/// Register a URL that receives every raised or resolved alert.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "alerts",
+    security(("session" = [])),
+    request_body = NewWebhook,
+    responses(
+        (status = 201, body = WebhookTarget),
+        (status = 422, description = "Not an http(s) URL", body = String)
+    )
+)]
+pub async fn create_webhook(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Json(webhook): Json<NewWebhook>,
+) -> impl IntoResponse {
+    if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Not an http(s) URL").into_response();
+    }
+    match state.webhook.create(webhook, now_millis()) {
+        Ok(webhook) => (StatusCode::CREATED, Json(webhook)).into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+/// Remove a registered webhook.
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "alerts",
+    security(("session" = [])),
+    params(("id" = i64, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Webhook removed"),
+        (status = 404, description = "Webhook not found", body = String)
+    )
+)]
+pub async fn delete_webhook(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match state.webhook.delete(id) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Webhook not found").into_response(),
+        Err(e) => internal_error(e).into_response(),
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct AuditQuery {
+    /// Earliest entry time (Unix ms), inclusive.
+    from: Option<u64>,
+    /// Latest entry time (Unix ms), inclusive.
+    to: Option<u64>,
+    /// Only entries of this actor.
+    actor: Option<String>,
+    /// Only entries with a larger id; pass the last id of the previous page.
+    #[serde(default)]
+    after: i64,
+    /// Page size; defaults to 100, at most 1000.
+    limit: Option<usize>,
+}
+
+/// Audit log of mutating requests, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    tag = "audit",
+    security(("session" = [])),
+    params(AuditQuery),
+    responses((status = 200, body = Vec<AuditEntry>))
+)]
+pub async fn list_audit(
+    _: Auth<Admin>,
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    let filter = AuditFilter {
+        from: query.from,
+        to: query.to,
+        actor: query.actor,
+    };
+    let limit = query
+        .limit
+        .unwrap_or(AUDIT_PAGE_SIZE)
+        .min(AUDIT_MAX_PAGE_SIZE);
+    state
+        .audit
+        .page(&filter, query.after, limit)
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Re-compute the audit hash chain.
+#[utoipa::path(
+    get,
+    path = "/api/audit/verify",
+    tag = "audit",
+    security(("session" = [])),
+    responses((status = 200, body = AuditVerification))
+)]
+pub async fn verify_audit(_: Auth<Admin>, State(state): State<AppState>) -> impl IntoResponse {
+    state.audit.verify().map(Json).map_err(internal_error)
+}
+
+/// Re-read the settings file (log level, rate limits, alert rules, webhooks, device
+/// aliases) without dropping connections. An invalid file changes nothing.
+#[utoipa::path(
+    post,
+    path = "/api/admin/reload",
+    tag = "admin",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Settings now in effect", body = Settings),
+        (status = 422, description = "Invalid settings; the previous ones stay in effect", body = String)
+    )
+)]
+pub async fn reload_settings(_: Auth<Admin>, State(state): State<AppState>) -> impl IntoResponse {
+    match settings::reload(&state) {
+        Ok(settings) => {
+            info!("Reloaded settings from {}", state.settings.path.display());
+            Json(settings).into_response()
         }
-        Err(_) => {
-            println!("No active WebSocket connections to broadcast to");
+        Err(e) => {
+            error!("{}; keeping the previous settings", e);
+            (StatusCode::UNPROCESSABLE_ENTITY, e).into_response()
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{sink, stream};
+    use tokio::sync::mpsc;
+
+    use crate::alerts::AlertSettings;
+    use crate::db::Db;
 
-    (StatusCode::OK, "Event received")
+    #[tokio::test]
+    async fn disconnected_clients_stop_consuming_their_queue() {
+        let alerts = AlertSettings {
+            inactivity_ms: 60_000,
+            low_battery: 20,
+        };
+        let state = AppState::new(16, Db::open(":memory:").unwrap(), alerts, None).unwrap();
+        let message_rx = state.rooms.join(DEFAULT_ROOM, None).unwrap();
+
+        let (outgoing_tx, mut outgoing) = mpsc::unbounded_channel();
+        let sender = Box::pin(sink::unfold(outgoing_tx, |tx, text: String| async move {
+            tx.send(text).map(|()| tx)
+        }));
+        let (incoming_tx, incoming) = mpsc::unbounded_channel::<String>();
+        let receiver = Box::pin(stream::unfold(incoming, |mut incoming| async move {
+            incoming.recv().await.map(|message| (message, incoming))
+        }));
+        let settings = ClientSettings {
+            queue: QueueSettings::default(),
+            batch_window: Duration::ZERO,
+            user: User {
+                username: "viewer".to_string(),
+                role: Role::Viewer,
+                created_at: 0,
+            },
+            ip: None,
+        };
+        // The test keeps the state, and with it the room's channel, alive like the server.
+        let client = tokio::spawn(handle_socket(
+            sender,
+            receiver,
+            state.clone(),
+            DEFAULT_ROOM.to_string(),
+            message_rx,
+            settings,
+        ));
+
+        let snapshot: serde_json::Value =
+            serde_json::from_str(&outgoing.recv().await.unwrap()).unwrap();
+        assert_eq!(snapshot["type"], "snapshot");
+        drop(incoming_tx);
+        let timeout = Duration::from_secs(1);
+        tokio::time::timeout(timeout, client)
+            .await
+            .unwrap()
+            .unwrap();
+        // The sender is only dropped once the task popping the queue has exited.
+        let closed = tokio::time::timeout(timeout, outgoing.recv()).await;
+        assert!(closed.unwrap().is_none());
+    }
 }
@@ -0,0 +1,95 @@
+use rusqlite::{params, Row};
+use serde::Serialize;
+
+use crate::db::Db;
+use crate::event::ButtonEvent;
+
+/// An ingested event as stored in the `events` table.
+#[derive(Clone, Debug, Serialize)]
+pub struct StoredEvent {
+    pub id: i64,
+    pub device: String,
+    pub button: String,
+    pub state: String,
+    pub timestamp: u64,
+    pub battery: Option<u8>,
+    pub alias: Option<String>,
+    pub duration_ms: Option<u64>,
+}
+
+/// Selects stored events; every bound is optional and inclusive.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub device: Option<String>,
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<StoredEvent> {
+    Ok(StoredEvent {
+        id: row.get(0)?,
+        device: row.get(1)?,
+        button: row.get(2)?,
+        state: row.get(3)?,
+        timestamp: row.get(4)?,
+        battery: row.get(5)?,
+        alias: row.get(6)?,
+        duration_ms: row.get(7)?,
+    })
+}
+
+/// Append-only log of every ingested event.
+#[derive(Clone)]
+pub struct EventHistory {
+    db: Db,
+}
+
+impl EventHistory {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+
+    pub fn append(&self, device: &str, event: &ButtonEvent) -> rusqlite::Result<()> {
+        self.db.conn().execute(
+            "INSERT INTO events (device, button, state, timestamp, battery, alias, duration_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                device,
+                event.button,
+                event.state,
+                event.timestamp,
+                event.battery,
+                event.alias,
+                event.duration_ms
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` matching events with an id above `after`, in id order. Callers
+    /// page through large results by passing the last id they received.
+    pub fn page(
+        &self,
+        filter: &EventFilter,
+        after: i64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<StoredEvent>> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, device, button, state, timestamp, battery, alias, duration_ms FROM events
+             WHERE id > ?1
+               AND (?2 IS NULL OR timestamp >= ?2)
+               AND (?3 IS NULL OR timestamp <= ?3)
+               AND (?4 IS NULL OR device = ?4)
+             ORDER BY id
+             LIMIT ?5",
+        )?;
+        let events = stmt
+            .query_map(
+                params![after, filter.from, filter.to, filter.device, limit],
+                from_row,
+            )?
+            .collect();
+        events
+    }
+}
@@ -0,0 +1,281 @@
+use axum::extract::ws::{Message, WebSocket};
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UdpSocket;
+
+use crate::config::DEFAULT_DEVICE_ID;
+use crate::event::{now_micros, now_millis, ButtonEvent, ServerMessage};
+use crate::logging::{debug, error, info, warn};
+use crate::state::AppState;
+
+/// Largest UDP payload we accept; one datagram carries exactly one event.
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+
+/// An event received on a raw transport, which has no headers to carry a bearer token.
+#[derive(Debug, Deserialize)]
+struct RawEvent {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(flatten)]
+    event: ButtonEvent,
+}
+
+/// Parses and validates a JSON-encoded event received on a raw transport. When an ingest
+/// token is configured the event must carry it in its `token` field.
+pub fn parse_event(bytes: &[u8], ingest_token: Option<&str>) -> Result<ButtonEvent, String> {
+    let raw: RawEvent = serde_json::from_slice(bytes).map_err(|e| e.to_string())?;
+    if let Some(expected) = ingest_token {
+        if raw.token.as_deref() != Some(expected) {
+            return Err("ingest token required".to_string());
+        }
+    }
+    raw.event.validate()?;
+    Ok(raw.event)
+}
+
+/// Whether the event's device is still within its ingest rate limit.
+pub fn within_rate_limit(state: &AppState, event: &ButtonEvent) -> bool {
+    let device = event.device.as_deref().unwrap_or(DEFAULT_DEVICE_ID);
+    state.rate_limits.allow_ingest(device, now_millis())
+}
+
+/// Common ingest path for every transport: stamps the trace, registers the device, updates
+/// its state and broadcasts the event.
+pub async fn ingest_event(state: &AppState, mut event: ButtonEvent) {
+    let trace = event.trace.get_or_insert_with(Default::default);
+    trace.server_ingested_at = Some(now_micros());
+    let device = event.device.as_deref().unwrap_or(DEFAULT_DEVICE_ID);
+    state.latency.record_ingest(device, trace);
+
+    debug!("Received button event: {:?}", event);
+
+    let address = event.device.as_deref().unwrap_or(DEFAULT_DEVICE_ID);
+    let room = match state.registry.touch(address, now_millis()) {
+        Ok(device) => {
+            event.alias = device.alias;
+            device.room
+        }
+        Err(e) => {
+            error!("Failed to register device {}: {}", address, e);
+            None
+        }
+    };
+
+    if let Err(e) = state.history.append(address, &event) {
+        error!("Failed to store event from {}: {}", address, e);
+    }
+
+    match state.alerts.observe(address, &event, now_millis()) {
+        Ok(alerts) => state.publish_alerts(alerts),
+        Err(e) => error!("Failed to update alerts of {}: {}", address, e),
+    }
+
+    state.record_event(&event).await;
+
+    state.broadcast(room.as_deref(), ServerMessage::Event(event));
+}
+
+/// One event sent by a listener on the ingest stream; `seq` increases with every event.
+#[derive(Debug, Deserialize)]
+pub struct IngestFrame {
+    pub seq: u64,
+    pub event: ButtonEvent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestStatus {
+    Accepted,
+    /// The event is invalid; sending it again will not help.
+    Rejected,
+    /// The device is over its rate limit; the event should be sent again later.
+    Retry,
+}
+
+/// Reply to an [`IngestFrame`], sent once the event has been ingested or rejected.
+#[derive(Debug, Serialize)]
+pub struct IngestAck {
+    pub seq: u64,
+    pub status: IngestStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Ingests one frame of the ingest stream and builds its acknowledgement.
+async fn ingest_frame(state: &AppState, frame: IngestFrame) -> IngestAck {
+    let (status, error) = match frame.event.validate() {
+        Err(e) => (IngestStatus::Rejected, Some(e)),
+        Ok(()) if !within_rate_limit(state, &frame.event) => (
+            IngestStatus::Retry,
+            Some("Event rate limit exceeded".to_string()),
+        ),
+        Ok(()) => {
+            ingest_event(state, frame.event).await;
+            (IngestStatus::Accepted, None)
+        }
+    };
+    IngestAck {
+        seq: frame.seq,
+        status,
+        error,
+    }
+}
+
+/// Ingests the frames of a listener's WebSocket one at a time, so events keep their order,
+/// and acknowledges each of them in turn.
+pub async fn serve_stream(socket: WebSocket, state: AppState) {
+    let (mut sender, mut receiver) = socket.split();
+
+    while let Some(result) = receiver.next().await {
+        let text = match result {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Ingest stream receive error: {}", e);
+                break;
+            }
+        };
+        let frame: IngestFrame = match serde_json::from_str(&text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!("Rejected frame on ingest stream: {}", e);
+                continue;
+            }
+        };
+
+        let ack = ingest_frame(&state, frame).await;
+        let Ok(json) = serde_json::to_string(&ack) else {
+            continue;
+        };
+        if sender.send(Message::Text(json.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Accepts newline-delimited JSON events on a Unix domain socket.
+#[cfg(unix)]
+pub async fn serve_unix(path: String, state: AppState) -> io::Result<()> {
+    use tokio::net::UnixListener;
+
+    // A socket file left behind by a previous run would make bind fail.
+    match std::fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(&path)?;
+    info!("📥 Accepting NDJSON events on unix:{}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stream).lines();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) if line.trim().is_empty() => {}
+                    Ok(Some(line)) => {
+                        match parse_event(line.as_bytes(), state.ingest_token.as_deref()) {
+                            Ok(event) if !within_rate_limit(&state, &event) => {
+                                warn!("Dropped event over the rate limit on unix socket");
+                            }
+                            Ok(event) => ingest_event(&state, event).await,
+                            Err(e) => warn!("Rejected event on unix socket: {}", e),
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        error!("Unix socket read error: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Accepts one JSON event per UDP datagram.
+pub async fn serve_udp(address: String, state: AppState) -> io::Result<()> {
+    let socket = UdpSocket::bind(&address).await?;
+    info!("📥 Accepting events on udp://{}", address);
+
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        match parse_event(&buf[..len], state.ingest_token.as_deref()) {
+            Ok(event) if !within_rate_limit(&state, &event) => {
+                warn!("Dropped event over the rate limit from udp {}", peer);
+            }
+            Ok(event) => ingest_event(&state, event).await,
+            Err(e) => warn!("Rejected event from udp {}: {}", peer, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::AlertSettings;
+    use crate::db::Db;
+    use crate::ratelimit::RateLimits;
+
+    #[tokio::test]
+    async fn rate_limited_frames_are_to_be_retried() {
+        let alerts = AlertSettings {
+            inactivity_ms: 60_000,
+            low_battery: 20,
+        };
+        let state = AppState::new(16, Db::open(":memory:").unwrap(), alerts, None).unwrap();
+        state.rate_limits.set_limits(RateLimits {
+            ingest_per_second: Some(1),
+            requests_per_minute: None,
+        });
+        let frame = |seq, button: &str| IngestFrame {
+            seq,
+            event: ButtonEvent {
+                button: button.to_string(),
+                state: "PRESSED".to_string(),
+                timestamp: 1,
+                device: Some("AA".to_string()),
+                battery: None,
+                duration_ms: None,
+                alias: None,
+                trace: None,
+            },
+        };
+
+        let statuses = [
+            ingest_frame(&state, frame(1, "A")).await.status,
+            ingest_frame(&state, frame(2, "A")).await.status,
+            ingest_frame(&state, frame(3, "")).await.status,
+        ];
+        assert_eq!(
+            statuses,
+            [
+                IngestStatus::Accepted,
+                IngestStatus::Retry,
+                IngestStatus::Rejected
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_events_need_the_ingest_token_once_set() {
+        let event = br#"{"button":"A","state":"PRESSED","timestamp":1}"#;
+        let with_token = br#"{"button":"A","state":"PRESSED","timestamp":1,"token":"secret"}"#;
+
+        assert!(parse_event(event, None).is_ok());
+        assert!(parse_event(event, Some("secret")).is_err());
+        assert!(parse_event(
+            br#"{"button":"A","state":"PRESSED","timestamp":1,"token":"guess"}"#,
+            Some("secret")
+        )
+        .is_err());
+        let parsed = parse_event(with_token, Some("secret")).unwrap();
+        assert_eq!((parsed.button.as_str(), parsed.timestamp), ("A", 1));
+    }
+}
@@ -0,0 +1,291 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::event::Trace;
+
+/// Upper bounds of the histogram buckets, in microseconds.
+const BUCKETS_US: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+/// Fixed-bucket histogram; the last slot counts values above the largest bound.
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS_US.len() + 1],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HistogramSummary {
+    pub count: u64,
+    pub mean_us: Option<u64>,
+    pub p50_us: Option<u64>,
+    pub p95_us: Option<u64>,
+    pub p99_us: Option<u64>,
+    /// `[upper bound in µs, count]` pairs.
+    #[schema(value_type = Vec<Vec<u64>>)]
+    pub buckets: Vec<(u64, u64)>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, value_us: u64) {
+        let index = BUCKETS_US
+            .iter()
+            .position(|&bound| value_us <= bound)
+            .unwrap_or(BUCKETS_US.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(value_us, Ordering::Relaxed);
+    }
+
+    fn counts(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Upper bound of the bucket containing the `q` quantile.
+    fn quantile(counts: &[u64], total: u64, q: f64) -> Option<u64> {
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64) * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(BUCKETS_US.get(i).copied().unwrap_or(u64::MAX));
+            }
+        }
+        None
+    }
+
+    pub fn summary(&self) -> HistogramSummary {
+        let counts = self.counts();
+        let count = counts.iter().sum();
+        let sum_us = self.sum_us.load(Ordering::Relaxed);
+        HistogramSummary {
+            count,
+            mean_us: (count > 0).then(|| sum_us / count),
+            p50_us: Self::quantile(&counts, count, 0.50),
+            p95_us: Self::quantile(&counts, count, 0.95),
+            p99_us: Self::quantile(&counts, count, 0.99),
+            buckets: BUCKETS_US.iter().copied().zip(counts).collect(),
+        }
+    }
+
+    fn write_prometheus(&self, out: &mut String, name: &str, hop: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS_US.iter().zip(self.counts()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{hop=\"{}\",le=\"{}\"}} {}",
+                name,
+                hop,
+                *bound as f64 / 1_000_000.0,
+                cumulative
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(
+            out,
+            "{}_bucket{{hop=\"{}\",le=\"+Inf\"}} {}",
+            name, hop, count
+        );
+        let _ = writeln!(out, "{}_sum{{hop=\"{}\"}} {}", name, hop, sum);
+        let _ = writeln!(out, "{}_count{{hop=\"{}\"}} {}", name, hop, count);
+    }
+}
+
+/// Reference point for turning a device's tick count into listener time.
+struct TickBaseline {
+    last_tick: u32,
+    /// Smallest `listener_received_at - device_tick` seen, in microseconds.
+    min_offset_us: i64,
+}
+
+/// Per-hop latency histograms for events travelling device → listener → server → browser.
+#[derive(Clone)]
+pub struct LatencyMetrics {
+    inner: Arc<Hops>,
+}
+
+struct Hops {
+    /// Device tick → listener notification receive, relative to the fastest delivery seen.
+    device_to_listener: Histogram,
+    /// Listener notification receive → listener send.
+    listener: Histogram,
+    /// Listener send → server ingest.
+    listener_to_server: Histogram,
+    /// Server ingest → WebSocket send, recorded once per client.
+    server_to_client: Histogram,
+    /// Listener notification receive → WebSocket send.
+    end_to_end: Histogram,
+    baselines: Mutex<HashMap<String, TickBaseline>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct LatencyReport {
+    pub device_to_listener: HistogramSummary,
+    pub listener: HistogramSummary,
+    pub listener_to_server: HistogramSummary,
+    pub server_to_client: HistogramSummary,
+    pub end_to_end: HistogramSummary,
+}
+
+impl Default for LatencyMetrics {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Hops {
+                device_to_listener: Histogram::new(),
+                listener: Histogram::new(),
+                listener_to_server: Histogram::new(),
+                server_to_client: Histogram::new(),
+                end_to_end: Histogram::new(),
+                baselines: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+impl LatencyMetrics {
+    /// Records the hops that are complete once the server has ingested an event from
+    /// `device`.
+    pub fn record_ingest(&self, device: &str, trace: &Trace) {
+        if let (Some(tick), Some(received)) = (trace.device_tick, trace.listener_received_at) {
+            self.record_device_hop(device, tick, received);
+        }
+        if let (Some(received), Some(sent)) = (trace.listener_received_at, trace.listener_sent_at) {
+            self.inner.listener.record(sent.saturating_sub(received));
+        }
+        if let (Some(sent), Some(ingested)) = (trace.listener_sent_at, trace.server_ingested_at) {
+            self.inner
+                .listener_to_server
+                .record(ingested.saturating_sub(sent));
+        }
+    }
+
+    /// The device's tick count and the listener's clock are unrelated, so the offset between
+    /// them is only known up to the transit time. The smallest offset seen is taken as the
+    /// fastest delivery, and each notification's hop is how much slower it was. The
+    /// baseline restarts when the tick goes backwards, i.e. the device rebooted or the tick
+    /// wrapped; clock drift between device and host is not corrected.
+    fn record_device_hop(&self, device: &str, tick: u32, received_us: u64) {
+        let offset_us = received_us as i64 - tick as i64 * 1000;
+        let mut baselines = self
+            .inner
+            .baselines
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let baseline = baselines.entry(device.to_string()).or_insert(TickBaseline {
+            last_tick: tick,
+            min_offset_us: offset_us,
+        });
+        if tick < baseline.last_tick {
+            baseline.min_offset_us = offset_us;
+        }
+        baseline.last_tick = tick;
+        baseline.min_offset_us = baseline.min_offset_us.min(offset_us);
+        self.inner
+            .device_to_listener
+            .record((offset_us - baseline.min_offset_us) as u64);
+    }
+
+    /// Records the hops that end when an event has been written to a WebSocket client.
+    pub fn record_ws_send(&self, trace: &Trace) {
+        let Some(ws_sent) = trace.ws_sent_at else {
+            return;
+        };
+        if let Some(ingested) = trace.server_ingested_at {
+            self.inner
+                .server_to_client
+                .record(ws_sent.saturating_sub(ingested));
+        }
+        if let Some(received) = trace.listener_received_at {
+            self.inner
+                .end_to_end
+                .record(ws_sent.saturating_sub(received));
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            device_to_listener: self.inner.device_to_listener.summary(),
+            listener: self.inner.listener.summary(),
+            listener_to_server: self.inner.listener_to_server.summary(),
+            server_to_client: self.inner.server_to_client.summary(),
+            end_to_end: self.inner.end_to_end.summary(),
+        }
+    }
+
+    /// Renders the histograms in the Prometheus text exposition format.
+    pub fn prometheus(&self) -> String {
+        const NAME: &str = "lgrb_event_latency_seconds";
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP {} Latency of button events per pipeline hop.",
+            NAME
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", NAME);
+        let hops = [
+            ("device_to_listener", &self.inner.device_to_listener),
+            ("listener", &self.inner.listener),
+            ("listener_to_server", &self.inner.listener_to_server),
+            ("server_to_client", &self.inner.server_to_client),
+            ("end_to_end", &self.inner.end_to_end),
+        ];
+        for (hop, histogram) in hops {
+            histogram.write_prometheus(&mut out, NAME, hop);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(tick: u32, received_us: u64) -> Trace {
+        Trace {
+            device_tick: Some(tick),
+            listener_received_at: Some(received_us),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn measures_the_device_hop_against_the_fastest_delivery() {
+        let metrics = LatencyMetrics::default();
+        metrics.record_ingest("AA", &trace(1_000, 50_000_000));
+        // Arrived 20 ms later than the first one would suggest.
+        metrics.record_ingest("AA", &trace(2_000, 51_020_000));
+        // A different device has its own baseline.
+        metrics.record_ingest("BB", &trace(10, 51_030_000));
+        // The device rebooted; its tick starts over.
+        metrics.record_ingest("AA", &trace(5, 60_000_000));
+        metrics.record_ingest("AA", &trace(1_005, 61_000_400));
+
+        let report = metrics.report().device_to_listener;
+        assert_eq!(report.count, 5);
+        assert_eq!(report.mean_us, Some((20_000 + 400) / 5));
+        assert_eq!(report.p50_us, Some(100));
+        assert_eq!(report.p99_us, Some(25_000));
+    }
+}
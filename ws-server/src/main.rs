@@ -1,36 +1,197 @@
+mod alerts;
+mod api;
+mod audit;
+mod auth;
+mod backplane;
+mod commands;
 mod config;
+mod db;
+mod device;
 mod event;
+mod export;
+mod framing;
 mod handlers;
+mod history;
+mod ingest;
+mod latency;
+mod logging;
+mod outbound;
+mod ratelimit;
+mod registry;
+mod rooms;
+mod settings;
 mod state;
+mod webhook;
+mod websocket;
 
-use axum::{routing::get, Router};
 use std::error::Error;
-use tower_http::services::ServeDir;
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use crate::config::{BROADCAST_CHANNEL_CAPACITY, SERVER_ADDRESS};
-use crate::handlers::{button_event, serve_html, websocket_handler};
+use crate::alerts::AlertSettings;
+use crate::backplane::Backplane;
+use crate::config::{
+    ADMIN_PASSWORD_ENV, ALERT_INACTIVITY_ENV, ALERT_INACTIVITY_MS, ALERT_SWEEP_INTERVAL_MS,
+    ALERT_WEBHOOK_ENV, BATCH_WINDOW_ENV, BROADCAST_CHANNEL_CAPACITY, CLIENT_OVERFLOW_ENV,
+    CLIENT_QUEUE_CAPACITY_ENV, COMMAND_RETENTION_MS, COMMAND_SWEEP_INTERVAL_MS, COMMAND_TIMEOUT_MS,
+    COMPRESSION_ENV, CONFIG_PATH_ENV, DATABASE_PATH, INGEST_TOKEN_ENV, LOW_BATTERY_ENV,
+    LOW_BATTERY_THRESHOLD, NATS_URL_ENV, NODE_ID_ENV, SERVER_ADDRESS, UDP_ADDRESS_ENV,
+    UNIX_SOCKET_ENV,
+};
+use crate::db::Db;
+use crate::event::{now_millis, ServerMessage};
+use crate::framing::FrameSettings;
+use crate::logging::{error, info};
+use crate::outbound::QueueSettings;
 use crate::state::AppState;
 
+async fn expire_commands(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_millis(COMMAND_SWEEP_INTERVAL_MS));
+    loop {
+        interval.tick().await;
+        let expired = state
+            .commands
+            .expire(now_millis(), COMMAND_TIMEOUT_MS, COMMAND_RETENTION_MS);
+        for command in expired {
+            info!("Command {} for {} timed out", command.id, command.device);
+            let device = command.device.clone();
+            state.broadcast_for_device(&device, ServerMessage::Command(command));
+        }
+    }
+}
+
+async fn sweep_alerts(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_millis(ALERT_SWEEP_INTERVAL_MS));
+    loop {
+        interval.tick().await;
+        match state.alerts.sweep(now_millis()) {
+            Ok(alerts) => state.publish_alerts(alerts),
+            Err(e) => error!("Failed to check for offline devices: {}", e),
+        }
+    }
+}
+
+/// Re-applies the settings file whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_sighup(state: AppState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match settings::reload(&state) {
+            Ok(_) => info!("Reloaded settings from {}", state.settings.path.display()),
+            Err(e) => error!("{}; keeping the previous settings", e),
+        }
+    }
+}
+
+/// Reads an optional setting from the environment, falling back to `default`.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("Invalid value for {}: {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let app_state = AppState::new(BROADCAST_CHANNEL_CAPACITY);
+    let db = Db::open(DATABASE_PATH)
+        .map_err(|e| format!("Failed to open database {}: {}", DATABASE_PATH, e))?;
+    let alert_settings = AlertSettings {
+        inactivity_ms: env_or(ALERT_INACTIVITY_ENV, ALERT_INACTIVITY_MS)?,
+        low_battery: env_or(LOW_BATTERY_ENV, LOW_BATTERY_THRESHOLD)?,
+    };
+    let alert_webhook = std::env::var(ALERT_WEBHOOK_ENV).ok();
+    let mut app_state = AppState::new(
+        BROADCAST_CHANNEL_CAPACITY,
+        db,
+        alert_settings,
+        alert_webhook,
+    )
+    .map_err(|e| format!("Failed to load server state: {}", e))?;
+    app_state.client_queue = QueueSettings {
+        capacity: env_or(CLIENT_QUEUE_CAPACITY_ENV, app_state.client_queue.capacity)?,
+        overflow: env_or(CLIENT_OVERFLOW_ENV, app_state.client_queue.overflow)?,
+    };
+    app_state.framing = FrameSettings {
+        compression: env_or(COMPRESSION_ENV, app_state.framing.compression)?,
+        batch_window_ms: env_or(BATCH_WINDOW_ENV, app_state.framing.batch_window_ms)?,
+    };
+    app_state.ingest_token = std::env::var(INGEST_TOKEN_ENV).ok();
+
+    let admin_password = std::env::var(ADMIN_PASSWORD_ENV).ok();
+    match app_state.users.bootstrap(admin_password, now_millis()) {
+        Ok(Some(password)) => info!("🔑 Created user admin with password {}", password),
+        Ok(None) => {}
+        Err(e) => return Err(format!("Failed to create admin user: {}", e).into()),
+    }
+
+    if let Ok(path) = std::env::var(CONFIG_PATH_ENV) {
+        app_state.settings.path = path.into();
+    }
+    settings::reload(&app_state)?;
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(app_state.clone()));
+
+    if let Ok(url) = std::env::var(NATS_URL_ENV) {
+        let node_id = std::env::var(NODE_ID_ENV)
+            .unwrap_or_else(|_| format!("{}-{}", std::process::id(), now_millis()));
+        let (backplane, subscriber) = Backplane::connect(&url, node_id.clone())
+            .await
+            .map_err(|e| format!("Failed to connect to backplane {}: {}", url, e))?;
+        app_state.backplane = Some(backplane);
+        tokio::spawn(backplane::relay(
+            subscriber,
+            node_id.clone(),
+            app_state.clone(),
+        ));
+        info!("🔗 Sharing broadcasts via {} as node {}", url, node_id);
+    }
 
-    let app = Router::new()
-        .route("/", get(serve_html))
-        .route("/ws", get(websocket_handler))
-        .route("/api/button", axum::routing::post(button_event))
-        .nest_service("/pkg", ServeDir::new("pkg"))
-        .with_state(app_state);
+    tokio::spawn(expire_commands(app_state.clone()));
+    tokio::spawn(sweep_alerts(app_state.clone()));
+
+    #[cfg(unix)]
+    if let Ok(path) = std::env::var(UNIX_SOCKET_ENV) {
+        let state = app_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ingest::serve_unix(path, state).await {
+                error!("Unix socket listener stopped: {}", e);
+            }
+        });
+    }
+
+    if let Ok(address) = std::env::var(UDP_ADDRESS_ENV) {
+        let state = app_state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ingest::serve_udp(address, state).await {
+                error!("UDP listener stopped: {}", e);
+            }
+        });
+    }
+
+    let app = api::router(app_state);
 
     let listener = tokio::net::TcpListener::bind(SERVER_ADDRESS)
         .await
         .map_err(|e| format!("Failed to bind to {}: {}", SERVER_ADDRESS, e))?;
 
-    println!("🚀 Web server running on http://{}", SERVER_ADDRESS);
+    info!("🚀 Web server running on http://{}", SERVER_ADDRESS);
 
-    axum::serve(listener, app)
-        .await
-        .map_err(|e| format!("Server error: {}", e))?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .map_err(|e| format!("Server error: {}", e))?;
 
     Ok(())
 }
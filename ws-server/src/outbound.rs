@@ -0,0 +1,230 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use utoipa::ToSchema;
+
+use crate::config::{CLIENT_QUEUE_CAPACITY, DEFAULT_DEVICE_ID};
+use crate::event::ServerMessage;
+
+/// What to do with a message when a client's outbound queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued message to make room.
+    #[default]
+    DropOldest,
+    /// Discard the incoming message.
+    DropNewest,
+    /// Replace the queued message about the same device button, command or alert, so the
+    /// client still ends up with the latest state; otherwise drop the oldest.
+    Coalesce,
+    /// Close the connection.
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_oldest" => Ok(Self::DropOldest),
+            "drop_newest" => Ok(Self::DropNewest),
+            "coalesce" => Ok(Self::Coalesce),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(format!("unknown overflow policy {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueueSettings {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        Self {
+            capacity: CLIENT_QUEUE_CAPACITY,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Identifies messages that supersede each other under [`OverflowPolicy::Coalesce`].
+fn coalesce_key(message: &ServerMessage) -> Option<String> {
+    match message {
+        ServerMessage::Event(event) => Some(format!(
+            "event/{}/{}",
+            event.device.as_deref().unwrap_or(DEFAULT_DEVICE_ID),
+            event.button.to_ascii_uppercase()
+        )),
+        ServerMessage::Command(command) => Some(format!("command/{}", command.id)),
+        ServerMessage::Alert(alert) => Some(format!("alert/{}", alert.id)),
+        ServerMessage::Snapshot { .. } => None,
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    messages: VecDeque<ServerMessage>,
+    closed: bool,
+    dropped: u64,
+}
+
+/// Bounded queue between a room's broadcast channel and one WebSocket connection, so a
+/// slow client only ever loses its own messages.
+pub struct ClientQueue {
+    settings: QueueSettings,
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+impl ClientQueue {
+    pub fn new(settings: QueueSettings) -> Self {
+        Self {
+            settings: QueueSettings {
+                capacity: settings.capacity.max(1),
+                ..settings
+            },
+            inner: Mutex::default(),
+            notify: Notify::new(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues a message, applying the overflow policy if the queue is full. Returns
+    /// `false` if the client must be disconnected.
+    pub fn push(&self, message: ServerMessage) -> bool {
+        let mut inner = self.lock();
+        if inner.closed {
+            return false;
+        }
+        if inner.messages.len() >= self.settings.capacity {
+            inner.dropped += 1;
+            match self.settings.overflow {
+                OverflowPolicy::DropOldest => {
+                    inner.messages.pop_front();
+                }
+                OverflowPolicy::DropNewest => return true,
+                OverflowPolicy::Coalesce => {
+                    let key = coalesce_key(&message);
+                    let superseded = key.as_ref().and_then(|key| {
+                        inner
+                            .messages
+                            .iter()
+                            .position(|queued| coalesce_key(queued).as_ref() == Some(key))
+                    });
+                    match superseded {
+                        Some(index) => inner.messages.remove(index),
+                        None => inner.messages.pop_front(),
+                    };
+                }
+                OverflowPolicy::Disconnect => {
+                    inner.closed = true;
+                    inner.messages.clear();
+                    drop(inner);
+                    self.notify.notify_one();
+                    return false;
+                }
+            }
+        }
+        inner.messages.push_back(message);
+        drop(inner);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Waits for the next message; `None` once the queue is closed.
+    pub async fn pop(&self) -> Option<ServerMessage> {
+        loop {
+            {
+                let mut inner = self.lock();
+                if inner.closed {
+                    return None;
+                }
+                if let Some(message) = inner.messages.pop_front() {
+                    return Some(message);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Wakes the consumer and makes every further `pop` return `None`.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Number of messages lost to the overflow policy so far.
+    pub fn dropped(&self) -> u64 {
+        self.lock().dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::ButtonEvent;
+
+    fn event(button: &str, state: &str) -> ServerMessage {
+        ServerMessage::Event(ButtonEvent {
+            button: button.to_string(),
+            state: state.to_string(),
+            timestamp: 0,
+            device: None,
+            battery: None,
+            duration_ms: None,
+            alias: None,
+            trace: None,
+        })
+    }
+
+    fn drain(queue: &ClientQueue) -> Vec<String> {
+        let mut inner = queue.lock();
+        inner
+            .messages
+            .drain(..)
+            .map(|message| match message {
+                ServerMessage::Event(e) => format!("{} {}", e.button, e.state),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn queue(overflow: OverflowPolicy) -> ClientQueue {
+        let queue = ClientQueue::new(QueueSettings {
+            capacity: 2,
+            overflow,
+        });
+        assert!(queue.push(event("A", "PRESSED")));
+        assert!(queue.push(event("B", "PRESSED")));
+        queue
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let q = queue(OverflowPolicy::DropOldest);
+        assert!(q.push(event("A", "RELEASED")));
+        assert_eq!(drain(&q), ["B PRESSED", "A RELEASED"]);
+
+        let q = queue(OverflowPolicy::DropNewest);
+        assert!(q.push(event("A", "RELEASED")));
+        assert_eq!(drain(&q), ["A PRESSED", "B PRESSED"]);
+
+        let q = queue(OverflowPolicy::Coalesce);
+        assert!(q.push(event("B", "RELEASED")));
+        assert_eq!(drain(&q), ["A PRESSED", "B RELEASED"]);
+
+        let q = queue(OverflowPolicy::Disconnect);
+        assert!(!q.push(event("A", "RELEASED")));
+        assert!(q.lock().closed);
+        assert_eq!(q.dropped(), 1);
+    }
+}
@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+
+use crate::audit::client_ip;
+use crate::config::{INGEST_PATH, RATE_LIMIT_MAX_KEYS};
+use crate::event::now_millis;
+use crate::state::AppState;
+
+/// Request budgets; unset limits are not enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimits {
+    /// Events accepted per device per second, on every ingest transport.
+    pub ingest_per_second: Option<u32>,
+    /// HTTP requests accepted per client IP per minute, event ingest excluded.
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Token bucket holding up to one period's worth of requests.
+struct Bucket {
+    tokens: f64,
+    updated: u64,
+}
+
+impl Bucket {
+    fn take(&mut self, limit: u32, period_ms: u64, now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.updated) as f64;
+        self.tokens = (self.tokens + elapsed * limit as f64 / period_ms as f64).min(limit as f64);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    limits: RateLimits,
+    ingest: HashMap<String, Bucket>,
+    requests: HashMap<String, Bucket>,
+}
+
+fn allow(
+    buckets: &mut HashMap<String, Bucket>,
+    key: &str,
+    limit: u32,
+    period_ms: u64,
+    now: u64,
+) -> bool {
+    if buckets.len() >= RATE_LIMIT_MAX_KEYS && !buckets.contains_key(key) {
+        // Forget clients that have been idle long enough to have a full bucket again.
+        buckets.retain(|_, bucket| now.saturating_sub(bucket.updated) < period_ms);
+    }
+    buckets
+        .entry(key.to_string())
+        .or_insert(Bucket {
+            tokens: limit as f64,
+            updated: now,
+        })
+        .take(limit, period_ms, now)
+}
+
+/// Per-device ingest and per-IP request limits; the limits can be replaced at runtime.
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl RateLimiter {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_limits(&self, limits: RateLimits) {
+        let mut inner = self.lock();
+        if inner.limits != limits {
+            *inner = Inner {
+                limits,
+                ..Inner::default()
+            };
+        }
+    }
+
+    /// Whether one more event from `device` fits its budget.
+    pub fn allow_ingest(&self, device: &str, now: u64) -> bool {
+        let inner = &mut *self.lock();
+        match inner.limits.ingest_per_second {
+            Some(limit) => allow(&mut inner.ingest, device, limit, 1000, now),
+            None => true,
+        }
+    }
+
+    /// Whether one more request from `ip` fits its budget.
+    pub fn allow_request(&self, ip: &str, now: u64) -> bool {
+        let inner = &mut *self.lock();
+        match inner.limits.requests_per_minute {
+            Some(limit) => allow(&mut inner.requests, ip, limit, 60_000, now),
+            None => true,
+        }
+    }
+}
+
+/// Middleware answering `429 Too Many Requests` once a client IP exceeds its budget.
+/// Event ingest has its own per-device budget and is not counted here.
+pub async fn limit_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ip) = client_ip(request.extensions()) else {
+        return next.run(request).await;
+    };
+    if request.uri().path() != INGEST_PATH && !state.rate_limits.allow_request(&ip, now_millis()) {
+        return (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+    }
+    next.run(request).await
+}
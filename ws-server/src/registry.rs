@@ -0,0 +1,215 @@
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+use crate::db::Db;
+
+/// A known device, keyed by its BLE address.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Device {
+    pub address: String,
+    pub alias: Option<String>,
+    pub owner: Option<String>,
+    pub location: Option<String>,
+    pub notes: Option<String>,
+    /// Room the device's events are broadcast to; unassigned devices use the default room.
+    pub room: Option<String>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewDevice {
+    pub address: String,
+    pub alias: Option<String>,
+    pub owner: Option<String>,
+    pub location: Option<String>,
+    pub notes: Option<String>,
+    pub room: Option<String>,
+}
+
+/// Fields to change on an existing device; omitted fields are left untouched and an empty
+/// string clears a field.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct DevicePatch {
+    pub alias: Option<String>,
+    pub owner: Option<String>,
+    pub location: Option<String>,
+    pub notes: Option<String>,
+    /// Room to move the device to; an empty string moves it back to the default room.
+    pub room: Option<String>,
+}
+
+const COLUMNS: &str = "address, alias, owner, location, notes, room, first_seen, last_seen";
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<Device> {
+    Ok(Device {
+        address: row.get(0)?,
+        alias: row.get(1)?,
+        owner: row.get(2)?,
+        location: row.get(3)?,
+        notes: row.get(4)?,
+        room: row.get(5)?,
+        first_seen: row.get(6)?,
+        last_seen: row.get(7)?,
+    })
+}
+
+#[derive(Clone)]
+pub struct Registry {
+    db: Db,
+}
+
+impl Registry {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+
+    pub fn list(&self) -> rusqlite::Result<Vec<Device>> {
+        let conn = self.db.conn();
+        let mut stmt =
+            conn.prepare(&format!("SELECT {} FROM devices ORDER BY address", COLUMNS))?;
+        let devices = stmt.query_map([], from_row)?.collect();
+        devices
+    }
+
+    pub fn get(&self, address: &str) -> rusqlite::Result<Option<Device>> {
+        self.db
+            .conn()
+            .query_row(
+                &format!("SELECT {} FROM devices WHERE address = ?1", COLUMNS),
+                [address],
+                from_row,
+            )
+            .optional()
+    }
+
+    /// Inserts a new device. Returns `None` if the address is already registered.
+    pub fn create(&self, device: NewDevice, now: u64) -> rusqlite::Result<Option<Device>> {
+        let inserted = self.db.conn().execute(
+            "INSERT OR IGNORE INTO devices
+                (address, alias, owner, location, notes, room, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)",
+            params![
+                device.address,
+                device.alias,
+                device.owner,
+                device.location,
+                device.notes,
+                device.room,
+                now
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(None);
+        }
+        self.get(&device.address)
+    }
+
+    pub fn update(&self, address: &str, patch: DevicePatch) -> rusqlite::Result<Option<Device>> {
+        let updated = self.db.conn().execute(
+            "UPDATE devices SET
+                alias = CASE WHEN ?2 IS NULL THEN alias ELSE NULLIF(?2, '') END,
+                owner = CASE WHEN ?3 IS NULL THEN owner ELSE NULLIF(?3, '') END,
+                location = CASE WHEN ?4 IS NULL THEN location ELSE NULLIF(?4, '') END,
+                notes = CASE WHEN ?5 IS NULL THEN notes ELSE NULLIF(?5, '') END,
+                room = CASE WHEN ?6 IS NULL THEN room ELSE NULLIF(?6, '') END
+             WHERE address = ?1",
+            params![
+                address,
+                patch.alias,
+                patch.owner,
+                patch.location,
+                patch.notes,
+                patch.room
+            ],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        self.get(address)
+    }
+
+    pub fn delete(&self, address: &str) -> rusqlite::Result<bool> {
+        let deleted = self
+            .db
+            .conn()
+            .execute("DELETE FROM devices WHERE address = ?1", [address])?;
+        Ok(deleted > 0)
+    }
+
+    /// Sets the alias of every listed device in one transaction, registering unknown
+    /// devices; an empty alias clears it.
+    pub fn set_aliases(
+        &self,
+        aliases: &BTreeMap<String, String>,
+        now: u64,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.db.conn();
+        let tx = conn.transaction()?;
+        for (address, alias) in aliases {
+            tx.execute(
+                "INSERT INTO devices (address, alias, first_seen, last_seen)
+                 VALUES (?1, NULLIF(?2, ''), ?3, ?3)
+                 ON CONFLICT(address) DO UPDATE SET alias = excluded.alias",
+                params![address, alias, now],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Registers the device if it is new and bumps its last-seen time.
+    pub fn touch(&self, address: &str, now: u64) -> rusqlite::Result<Device> {
+        self.db.conn().execute(
+            "INSERT INTO devices (address, first_seen, last_seen) VALUES (?1, ?2, ?2)
+             ON CONFLICT(address) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
+            params![address, now],
+        )?;
+        self.get(address)?
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_and_clears_fields() {
+        let registry = Registry::new(Db::open(":memory:").unwrap());
+        let device = NewDevice {
+            address: "AA:BB:CC:DD:EE:FF".to_string(),
+            alias: Some("Left".to_string()),
+            owner: Some("ana".to_string()),
+            location: Some("Lab 1".to_string()),
+            notes: None,
+            room: None,
+        };
+        let created = registry.create(device, 10).unwrap().unwrap();
+        assert_eq!(created.alias.as_deref(), Some("Left"));
+        assert_eq!(created.first_seen, 10);
+
+        let patch = DevicePatch {
+            alias: Some("Right".to_string()),
+            location: Some(String::new()),
+            ..Default::default()
+        };
+        let updated = registry.update(&created.address, patch).unwrap().unwrap();
+        assert_eq!(updated.alias.as_deref(), Some("Right"));
+        assert_eq!(updated.owner.as_deref(), Some("ana"));
+        assert_eq!(updated.location, None);
+
+        let patch = DevicePatch {
+            alias: Some(String::new()),
+            owner: Some(String::new()),
+            ..Default::default()
+        };
+        let cleared = registry.update(&created.address, patch).unwrap().unwrap();
+        assert_eq!((cleared.alias, cleared.owner), (None, None));
+        assert!(registry
+            .update("11:22:33:44:55:66", DevicePatch::default())
+            .unwrap()
+            .is_none());
+    }
+}
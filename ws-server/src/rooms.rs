@@ -0,0 +1,267 @@
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+use crate::db::Db;
+use crate::event::ServerMessage;
+
+/// Room used by `/ws` and by devices that are not assigned to a room.
+pub const DEFAULT_ROOM: &str = "default";
+
+/// Longest allowed room name.
+pub const ROOM_NAME_MAX: usize = 32;
+
+/// Largest broadcast channel capacity a room may have. Tokio allocates the whole buffer
+/// up front, so larger values would waste memory or fail outright.
+pub const MAX_ROOM_CAPACITY: usize = 10_000;
+
+/// Checks a new room name. Names end up in URLs and in backplane messages, so they are
+/// limited to ASCII letters, digits, `-` and `_`.
+pub fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("Room name is empty");
+    }
+    if name.len() > ROOM_NAME_MAX {
+        return Err("Room name is longer than 32 characters");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Room name may only contain letters, digits, '-' and '_'");
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Room {
+    pub name: String,
+    pub capacity: usize,
+    /// Whether joining the room requires a token.
+    pub protected: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewRoom {
+    pub name: String,
+    /// Broadcast channel capacity; defaults to the server-wide capacity.
+    pub capacity: Option<usize>,
+    /// Token clients must pass as `?token=` when joining.
+    pub token: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum JoinError {
+    NotFound,
+    Unauthorized,
+}
+
+struct RoomChannel {
+    tx: broadcast::Sender<ServerMessage>,
+    capacity: usize,
+    token: Option<String>,
+}
+
+impl RoomChannel {
+    fn new(capacity: usize, token: Option<String>) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            capacity,
+            token,
+        }
+    }
+}
+
+/// Named rooms, each with its own broadcast channel, persisted in the `rooms` table.
+#[derive(Clone)]
+pub struct RoomHub {
+    db: Db,
+    rooms: Arc<RwLock<HashMap<String, RoomChannel>>>,
+    default_capacity: usize,
+}
+
+impl RoomHub {
+    /// Loads the persisted rooms and creates the default room.
+    pub fn load(db: Db, default_capacity: usize) -> rusqlite::Result<Self> {
+        let mut rooms = HashMap::new();
+        rooms.insert(
+            DEFAULT_ROOM.to_string(),
+            RoomChannel::new(default_capacity, None),
+        );
+        {
+            let conn = db.conn();
+            let mut stmt = conn.prepare("SELECT name, capacity, token FROM rooms")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, usize>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (name, capacity, token) = row?;
+                // Rooms stored before the limit existed must not stop the server starting.
+                let capacity = capacity.clamp(1, MAX_ROOM_CAPACITY);
+                rooms.insert(name, RoomChannel::new(capacity, token));
+            }
+        }
+        Ok(Self {
+            db,
+            rooms: Arc::new(RwLock::new(rooms)),
+            default_capacity,
+        })
+    }
+
+    pub fn list(&self) -> Vec<Room> {
+        let rooms = self.rooms.read().unwrap_or_else(|e| e.into_inner());
+        let mut list: Vec<Room> = rooms
+            .iter()
+            .map(|(name, channel)| Room {
+                name: name.clone(),
+                capacity: channel.capacity,
+                protected: channel.token.is_some(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        let rooms = self.rooms.read().unwrap_or_else(|e| e.into_inner());
+        rooms.contains_key(name)
+    }
+
+    /// Creates a room, with its capacity clamped to `1..=MAX_ROOM_CAPACITY`. Returns `None`
+    /// if a room with that name already exists.
+    pub fn create(&self, room: NewRoom) -> rusqlite::Result<Option<Room>> {
+        let capacity = room
+            .capacity
+            .unwrap_or(self.default_capacity)
+            .clamp(1, MAX_ROOM_CAPACITY);
+        let mut rooms = self.rooms.write().unwrap_or_else(|e| e.into_inner());
+        if rooms.contains_key(&room.name) {
+            return Ok(None);
+        }
+        // The row is only written once the channel exists, so a room that cannot be set up
+        // is never persisted and reloaded on every start.
+        let channel = RoomChannel::new(capacity, room.token.clone());
+        self.db.conn().execute(
+            "INSERT INTO rooms (name, capacity, token) VALUES (?1, ?2, ?3)",
+            params![room.name, capacity, room.token],
+        )?;
+        let protected = room.token.is_some();
+        rooms.insert(room.name.clone(), channel);
+        Ok(Some(Room {
+            name: room.name,
+            capacity,
+            protected,
+        }))
+    }
+
+    /// Deletes a room; its clients are disconnected and its devices fall back to the
+    /// default room. The default room cannot be deleted.
+    pub fn delete(&self, name: &str) -> rusqlite::Result<bool> {
+        if name == DEFAULT_ROOM {
+            return Ok(false);
+        }
+        let mut rooms = self.rooms.write().unwrap_or_else(|e| e.into_inner());
+        self.db
+            .conn()
+            .execute("DELETE FROM rooms WHERE name = ?1", [name])?;
+        Ok(rooms.remove(name).is_some())
+    }
+
+    pub fn join(
+        &self,
+        name: &str,
+        token: Option<&str>,
+    ) -> Result<broadcast::Receiver<ServerMessage>, JoinError> {
+        let rooms = self.rooms.read().unwrap_or_else(|e| e.into_inner());
+        let channel = rooms.get(name).ok_or(JoinError::NotFound)?;
+        match &channel.token {
+            Some(expected) if token != Some(expected.as_str()) => Err(JoinError::Unauthorized),
+            _ => Ok(channel.tx.subscribe()),
+        }
+    }
+
+    /// Sends a message to a room, falling back to the default room if it does not exist.
+    /// Returns the number of receivers.
+    pub fn send(&self, room: Option<&str>, message: ServerMessage) -> usize {
+        let rooms = self.rooms.read().unwrap_or_else(|e| e.into_inner());
+        let channel = room
+            .and_then(|name| rooms.get(name))
+            .or_else(|| rooms.get(DEFAULT_ROOM));
+        channel
+            .and_then(|channel| channel.tx.send(message).ok())
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(name: &str, token: Option<&str>) -> NewRoom {
+        NewRoom {
+            name: name.to_string(),
+            capacity: Some(8),
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn creates_joins_and_deletes_rooms() {
+        let db = Db::open(":memory:").unwrap();
+        let hub = RoomHub::load(db.clone(), 16).unwrap();
+        let created = hub.create(room("lab-1", Some("secret"))).unwrap().unwrap();
+        assert!(created.protected);
+        assert!(hub.create(room("lab-1", None)).unwrap().is_none());
+
+        assert!(matches!(
+            hub.join("lab-1", None),
+            Err(JoinError::Unauthorized)
+        ));
+        assert!(matches!(hub.join("lab-2", None), Err(JoinError::NotFound)));
+        let mut receiver = hub.join("lab-1", Some("secret")).unwrap();
+        let snapshot = ServerMessage::Snapshot {
+            devices: Vec::new(),
+        };
+        assert_eq!(hub.send(Some("lab-1"), snapshot), 1);
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(ServerMessage::Snapshot { .. })
+        ));
+
+        // Rooms survive a restart.
+        let reloaded = RoomHub::load(db.clone(), 16).unwrap();
+        assert!(reloaded.exists("lab-1"));
+        assert!(reloaded.delete("lab-1").unwrap());
+        assert!(!reloaded.delete(DEFAULT_ROOM).unwrap());
+        assert!(!reloaded.exists("lab-1"));
+
+        let huge = NewRoom {
+            capacity: Some(usize::MAX),
+            ..room("lab-3", None)
+        };
+        assert_eq!(
+            hub.create(huge).unwrap().unwrap().capacity,
+            MAX_ROOM_CAPACITY
+        );
+        db.conn()
+            .execute(
+                "UPDATE rooms SET capacity = ?1 WHERE name = 'lab-3'",
+                [i64::MAX],
+            )
+            .unwrap();
+        assert!(RoomHub::load(db.clone(), 16).unwrap().exists("lab-3"));
+
+        assert!(validate_name("team_red-2").is_ok());
+        for name in ["", "lab 1", "lab/1", "lab.1", "läb", &"x".repeat(33)] {
+            assert!(validate_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use utoipa::ToSchema;
+
+use crate::alerts::AlertSettings;
+use crate::event::now_millis;
+use crate::logging::{self, LogLevel};
+use crate::ratelimit::RateLimits;
+use crate::state::AppState;
+
+/// Alert thresholds; unset values keep the ones from the environment.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertRules {
+    /// Silence after which a device is reported offline.
+    pub offline_after_ms: Option<u64>,
+    /// Battery percentage below which a low-battery alert is raised.
+    pub low_battery: Option<u8>,
+}
+
+/// Settings that can change while the server runs, read from a TOML file on startup, on
+/// SIGHUP and on `POST /api/admin/reload`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub log_level: LogLevel,
+    pub rate_limits: RateLimits,
+    pub rules: AlertRules,
+    /// Alert webhook URLs, in addition to `WS_ALERT_WEBHOOK` and those added via the API.
+    pub webhooks: Vec<String>,
+    /// Device aliases by BLE address; an empty alias clears it.
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Settings {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let settings: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let limits = [
+            ("ingest_per_second", self.rate_limits.ingest_per_second),
+            ("requests_per_minute", self.rate_limits.requests_per_minute),
+        ];
+        for (name, limit) in limits {
+            if limit == Some(0) {
+                return Err(format!("rate_limits.{} must be positive", name));
+            }
+        }
+        if self.rules.offline_after_ms == Some(0) {
+            return Err("rules.offline_after_ms must be positive".to_string());
+        }
+        if self.rules.low_battery.is_some_and(|level| level > 100) {
+            return Err("rules.low_battery must be a percentage".to_string());
+        }
+        if let Some(url) = self
+            .webhooks
+            .iter()
+            .find(|url| !url.starts_with("http://") && !url.starts_with("https://"))
+        {
+            return Err(format!("webhook {} is not an http(s) URL", url));
+        }
+        if self.aliases.keys().any(String::is_empty) {
+            return Err("aliases must not have an empty address".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Where the settings file lives and what unset fields fall back to.
+#[derive(Clone, Debug)]
+pub struct SettingsSource {
+    pub path: PathBuf,
+    /// Alert thresholds from the environment.
+    pub alert_defaults: AlertSettings,
+}
+
+impl SettingsSource {
+    /// Reads and validates the file; a missing file means every setting is left unset.
+    pub fn read(&self) -> Result<Settings, String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => Settings::parse(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(e) => Err(e.to_string()),
+        }
+        .map_err(|e| format!("Invalid settings file {}: {}", self.path.display(), e))
+    }
+}
+
+/// Re-reads the settings file and applies it. If the file cannot be read, is invalid or
+/// the aliases cannot be stored, nothing changes and the previous settings stay in effect.
+pub fn reload(state: &AppState) -> Result<Settings, String> {
+    let source = &state.settings;
+    let settings = source.read()?;
+
+    state
+        .registry
+        .set_aliases(&settings.aliases, now_millis())
+        .map_err(|e| format!("Failed to store device aliases: {}", e))?;
+    logging::set_level(settings.log_level);
+    state.rate_limits.set_limits(settings.rate_limits);
+    state.alerts.set_settings(AlertSettings {
+        inactivity_ms: settings
+            .rules
+            .offline_after_ms
+            .unwrap_or(source.alert_defaults.inactivity_ms),
+        low_battery: settings
+            .rules
+            .low_battery
+            .unwrap_or(source.alert_defaults.low_battery),
+    });
+    state.webhook.set_configured(settings.webhooks.clone());
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::event::ButtonEvent;
+
+    /// Whether a battery reading of 25% raises a low-battery alert for a new device.
+    fn low_at_25(state: &AppState, device: &str) -> bool {
+        let event = ButtonEvent {
+            button: "BATTERY".to_string(),
+            state: "REPORTED".to_string(),
+            timestamp: 0,
+            device: Some(device.to_string()),
+            battery: Some(25),
+            duration_ms: None,
+            alias: None,
+            trace: None,
+        };
+        !state.alerts.observe(device, &event, 0).unwrap().is_empty()
+    }
+
+    #[test]
+    fn invalid_settings_keep_the_previous_ones() {
+        let path = std::env::temp_dir().join(format!("ws-server-{}.toml", std::process::id()));
+        let alerts = AlertSettings {
+            inactivity_ms: 60_000,
+            low_battery: 20,
+        };
+        let mut state = AppState::new(16, Db::open(":memory:").unwrap(), alerts, None).unwrap();
+        state.settings.path = path.clone();
+
+        std::fs::write(
+            &path,
+            "[rules]\nlow_battery = 30\n\n[aliases]\n\"AA\" = \"Team red\"\n",
+        )
+        .unwrap();
+        assert!(!low_at_25(&state, "A0"));
+        reload(&state).unwrap();
+        assert!(low_at_25(&state, "A1"));
+        let device = state.registry.get("AA").unwrap().unwrap();
+        assert_eq!(device.alias.as_deref(), Some("Team red"));
+
+        for invalid in [
+            "[rules]\nlow_battery = 101\n",
+            "[rules]\nlow_battery = 10\nunknown = 1\n",
+            "webhooks = [\"ftp://example.com\"]\n",
+            "log_level = \"loud\"\n",
+        ] {
+            std::fs::write(&path, invalid).unwrap();
+            assert!(reload(&state).is_err(), "accepted {:?}", invalid);
+        }
+        assert!(low_at_25(&state, "A2"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,155 @@
-use tokio::sync::broadcast;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
-use crate::event::ButtonEvent;
+use crate::alerts::{Alert, AlertSettings, Alerts};
+use crate::audit::AuditLog;
+use crate::auth::Users;
+use crate::backplane::Backplane;
+use crate::commands::CommandQueue;
+use crate::config::{CONFIG_PATH, DEFAULT_DEVICE_ID};
+use crate::db::Db;
+use crate::device::DeviceState;
+use crate::event::{ButtonEvent, ServerMessage};
+use crate::framing::FrameSettings;
+use crate::history::EventHistory;
+use crate::latency::LatencyMetrics;
+use crate::logging::{debug, error, info};
+use crate::outbound::QueueSettings;
+use crate::ratelimit::RateLimiter;
+use crate::registry::Registry;
+use crate::rooms::{RoomHub, DEFAULT_ROOM};
+use crate::settings::SettingsSource;
+use crate::webhook::Webhook;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub button_tx: broadcast::Sender<ButtonEvent>,
+    pub rooms: RoomHub,
+    pub devices: Arc<RwLock<HashMap<String, DeviceState>>>,
+    pub registry: Registry,
+    pub history: EventHistory,
+    pub commands: CommandQueue,
+    pub alerts: Alerts,
+    pub webhook: Webhook,
+    pub users: Users,
+    pub audit: AuditLog,
+    pub rate_limits: RateLimiter,
+    /// Source of the settings that can be reloaded at runtime.
+    pub settings: SettingsSource,
+    /// Bearer token required from ble-listener; ingest is open if `None`.
+    pub ingest_token: Option<String>,
+    pub latency: LatencyMetrics,
+    /// Shares broadcasts with other instances when configured.
+    pub backplane: Option<Backplane>,
+    /// Outbound queue of each WebSocket connection.
+    pub client_queue: QueueSettings,
+    /// Compression and batching of WebSocket frames.
+    pub framing: FrameSettings,
 }
 
 impl AppState {
-    pub fn new(capacity: usize) -> Self {
-        let (button_tx, _) = broadcast::channel(capacity);
-        Self { button_tx }
+    pub fn new(
+        capacity: usize,
+        db: Db,
+        alert_settings: AlertSettings,
+        alert_webhook: Option<String>,
+    ) -> rusqlite::Result<Self> {
+        Ok(Self {
+            rooms: RoomHub::load(db.clone(), capacity)?,
+            alerts: Alerts::load(db.clone(), alert_settings)?,
+            webhook: Webhook::new(db.clone(), alert_webhook),
+            users: Users::new(db.clone()),
+            audit: AuditLog::new(db.clone()),
+            rate_limits: RateLimiter::default(),
+            settings: SettingsSource {
+                path: CONFIG_PATH.into(),
+                alert_defaults: alert_settings,
+            },
+            ingest_token: None,
+            devices: Arc::new(RwLock::new(HashMap::new())),
+            registry: Registry::new(db.clone()),
+            history: EventHistory::new(db),
+            commands: CommandQueue::default(),
+            latency: LatencyMetrics::default(),
+            backplane: None,
+            client_queue: QueueSettings::default(),
+            framing: FrameSettings::default(),
+        })
+    }
+
+    /// Sends a message to every WebSocket client in `room` (the default room if `None`),
+    /// on this node and, through the backplane, on every other node.
+    pub fn broadcast(&self, room: Option<&str>, message: ServerMessage) {
+        if let Some(backplane) = &self.backplane {
+            backplane.publish(room, &message);
+        }
+        match self.rooms.send(room, message) {
+            0 => debug!("No active WebSocket connections to broadcast to"),
+            receiver_count => debug!("Message broadcasted to {} receivers", receiver_count),
+        }
+    }
+
+    /// Sends a message to the room the device is assigned to.
+    pub fn broadcast_for_device(&self, device: &str, message: ServerMessage) {
+        let room = match self.registry.get(device) {
+            Ok(device) => device.and_then(|d| d.room),
+            Err(e) => {
+                error!("Failed to look up room of {}: {}", device, e);
+                None
+            }
+        };
+        self.broadcast(room.as_deref(), message);
+    }
+
+    /// Broadcasts raised or resolved alerts to their device's room and the webhook.
+    pub fn publish_alerts(&self, alerts: Vec<Alert>) {
+        for alert in alerts {
+            match alert.resolved_at {
+                Some(_) => info!(
+                    "Alert {} resolved: {} {:?}",
+                    alert.id, alert.device, alert.kind
+                ),
+                None => info!(
+                    "Alert {} raised: {} {:?} ({})",
+                    alert.id, alert.device, alert.kind, alert.message
+                ),
+            }
+            self.webhook.notify(&alert);
+            let device = alert.device.clone();
+            self.broadcast_for_device(&device, ServerMessage::Alert(alert));
+        }
+    }
+
+    pub async fn record_event(&self, event: &ButtonEvent) {
+        let id = event.device.as_deref().unwrap_or(DEFAULT_DEVICE_ID);
+        let mut devices = self.devices.write().await;
+        devices
+            .entry(id.to_string())
+            .or_insert_with(|| DeviceState::new(id))
+            .apply(event);
+    }
+
+    /// State of the devices whose events are broadcast to `room`.
+    pub async fn device_snapshot(&self, room: &str) -> Vec<DeviceState> {
+        let rooms: HashMap<String, String> = match self.registry.list() {
+            Ok(registered) => registered
+                .into_iter()
+                .filter_map(|d| d.room.map(|room| (d.address, room)))
+                .filter(|(_, room)| self.rooms.exists(room))
+                .collect(),
+            Err(e) => {
+                error!("Failed to list devices: {}", e);
+                HashMap::new()
+            }
+        };
+        let in_room = |id: &str| rooms.get(id).map_or(DEFAULT_ROOM, String::as_str) == room;
+        let devices = self.devices.read().await;
+        let mut snapshot: Vec<DeviceState> = devices
+            .values()
+            .filter(|d| in_room(&d.id))
+            .cloned()
+            .collect();
+        snapshot.sort_by(|a, b| a.id.cmp(&b.id));
+        snapshot
     }
 }
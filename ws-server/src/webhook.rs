@@ -0,0 +1,109 @@
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use utoipa::ToSchema;
+
+use crate::alerts::Alert;
+use crate::db::Db;
+use crate::logging::{error, info};
+
+/// A URL registered by an admin to receive alerts.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct WebhookTarget {
+    pub id: i64,
+    pub url: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhook {
+    pub url: String,
+}
+
+fn from_row(row: &Row<'_>) -> rusqlite::Result<WebhookTarget> {
+    Ok(WebhookTarget {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        created_at: row.get(2)?,
+    })
+}
+
+/// Posts alerts as JSON to an optional webhook URL from the environment, the URLs of the
+/// settings file and the URLs stored in the `webhooks` table.
+#[derive(Clone)]
+pub struct Webhook {
+    db: Db,
+    client: reqwest::Client,
+    url: Option<String>,
+    configured: Arc<RwLock<Vec<String>>>,
+}
+
+impl Webhook {
+    pub fn new(db: Db, url: Option<String>) -> Self {
+        Self {
+            db,
+            client: reqwest::Client::new(),
+            url,
+            configured: Arc::default(),
+        }
+    }
+
+    /// Replaces the URLs taken from the settings file.
+    pub fn set_configured(&self, urls: Vec<String>) {
+        *self.configured.write().unwrap_or_else(|e| e.into_inner()) = urls;
+    }
+
+    pub fn list(&self) -> rusqlite::Result<Vec<WebhookTarget>> {
+        let conn = self.db.conn();
+        let mut stmt = conn.prepare("SELECT id, url, created_at FROM webhooks ORDER BY id")?;
+        let targets = stmt.query_map([], from_row)?.collect();
+        targets
+    }
+
+    pub fn create(&self, webhook: NewWebhook, now: u64) -> rusqlite::Result<WebhookTarget> {
+        let conn = self.db.conn();
+        conn.execute(
+            "INSERT INTO webhooks (url, created_at) VALUES (?1, ?2)",
+            params![webhook.url, now],
+        )?;
+        Ok(WebhookTarget {
+            id: conn.last_insert_rowid(),
+            url: webhook.url,
+            created_at: now,
+        })
+    }
+
+    pub fn delete(&self, id: i64) -> rusqlite::Result<bool> {
+        let deleted = self
+            .db
+            .conn()
+            .execute("DELETE FROM webhooks WHERE id = ?1", [id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Sends the alert in the background; failures are only logged.
+    pub fn notify(&self, alert: &Alert) {
+        let mut urls: Vec<String> = self.url.iter().cloned().collect();
+        urls.extend(
+            self.configured
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .cloned(),
+        );
+        match self.list() {
+            Ok(targets) => urls.extend(targets.into_iter().map(|t| t.url)),
+            Err(e) => error!("Failed to list webhooks: {}", e),
+        }
+        for url in urls {
+            let request = self.client.post(&url).json(alert);
+            let id = alert.id;
+            tokio::spawn(async move {
+                match request.send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => info!("Alert {} delivered to {}", id, url),
+                    Err(e) => error!("Failed to deliver alert {} to {}: {}", id, url, e),
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{header::SEC_WEBSOCKET_EXTENSIONS, request::Parts, Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{sink, stream, Sink, Stream};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper_util::rt::TokioIo;
+use soketto::connection::{Error, Mode};
+use soketto::extension::deflate::Deflate;
+use soketto::handshake::http::{is_upgrade_request, Server};
+use soketto::Data;
+use std::future::Future;
+use std::pin::Pin;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+use crate::logging::{error, warn};
+
+type Socket = Compat<TokioIo<Upgraded>>;
+
+/// Sends text messages to a client.
+pub type TextSink = Pin<Box<dyn Sink<String, Error = Error> + Send>>;
+/// Text messages received from a client; ends when the client closes the connection.
+pub type TextStream = Pin<Box<dyn Stream<Item = String> + Send>>;
+
+/// Extracts a WebSocket upgrade request, like Axum's `WebSocketUpgrade`, but completes the
+/// handshake with a WebSocket implementation that supports the `permessage-deflate`
+/// extension (RFC 7692), which Axum's does not.
+pub struct ClientUpgrade {
+    request: Request<()>,
+    on_upgrade: OnUpgrade,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientUpgrade {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let mut request = Request::new(());
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = parts.uri.clone();
+        *request.headers_mut() = parts.headers.clone();
+        if parts.method != Method::GET || !is_upgrade_request(&request) {
+            return Err((StatusCode::BAD_REQUEST, "Expected a WebSocket upgrade").into_response());
+        }
+        let Some(on_upgrade) = parts.extensions.remove::<OnUpgrade>() else {
+            return Err((
+                StatusCode::UPGRADE_REQUIRED,
+                "Connection cannot be upgraded",
+            )
+                .into_response());
+        };
+        Ok(Self {
+            request,
+            on_upgrade,
+        })
+    }
+}
+
+impl ClientUpgrade {
+    /// Answers the handshake and, once the connection is upgraded, hands it to `callback`.
+    /// With `compression`, a client offering `permessage-deflate` gets its messages
+    /// compressed and may compress its own.
+    pub fn on_upgrade<F, Fut>(self, compression: bool, callback: F) -> Response
+    where
+        F: FnOnce(TextSink, TextStream) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut server = Server::new();
+        if compression {
+            server.add_extension(Box::new(Deflate::new(Mode::Server)));
+        }
+        let mut response = match server.receive_request(&self.request) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Rejected WebSocket handshake: {}", e);
+                return (StatusCode::BAD_REQUEST, "Invalid WebSocket handshake").into_response();
+            }
+        };
+
+        // The header is sent even when no extension was agreed on; leave it out then, as
+        // an empty value is not valid.
+        let headers = response.headers_mut();
+        if headers
+            .get(SEC_WEBSOCKET_EXTENSIONS)
+            .is_some_and(|value| value.is_empty())
+        {
+            headers.remove(SEC_WEBSOCKET_EXTENSIONS);
+        }
+
+        let on_upgrade = self.on_upgrade;
+        tokio::spawn(async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    error!("WebSocket upgrade failed: {}", e);
+                    return;
+                }
+            };
+            let (sender, receiver) = server
+                .into_builder(TokioIo::new(upgraded).compat())
+                .finish();
+            callback(text_sink(sender), text_stream(receiver)).await;
+        });
+        response.map(|()| Body::empty())
+    }
+}
+
+fn text_sink(sender: soketto::Sender<Socket>) -> TextSink {
+    Box::pin(sink::unfold(
+        sender,
+        |mut sender, text: String| async move {
+            sender.send_text_owned(text).await?;
+            sender.flush().await?;
+            Ok(sender)
+        },
+    ))
+}
+
+/// Binary messages are skipped; a receive error ends the stream like a close.
+fn text_stream(receiver: soketto::Receiver<Socket>) -> TextStream {
+    Box::pin(stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            let mut message = Vec::new();
+            match receiver.receive_data(&mut message).await {
+                Ok(Data::Text(_)) => match String::from_utf8(message) {
+                    Ok(text) => return Some((text, receiver)),
+                    Err(e) => warn!("Ignoring invalid WebSocket text message: {}", e),
+                },
+                Ok(Data::Binary(_)) => {}
+                Err(Error::Closed) => return None,
+                Err(e) => {
+                    error!("WebSocket receive error: {}", e);
+                    return None;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Serves a route that echoes every text message back to the client.
+    async fn echo_server(compression: bool) -> std::net::SocketAddr {
+        let app = Router::new().route(
+            "/ws",
+            get(move |upgrade: ClientUpgrade| async move {
+                upgrade.on_upgrade(compression, |mut sink, mut stream| async move {
+                    while let Some(text) = stream.next().await {
+                        sink.send(text).await.unwrap();
+                    }
+                })
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        address
+    }
+
+    /// Opens a connection with a raw handshake and returns it with the response head.
+    async fn handshake(address: std::net::SocketAddr, extensions: &str) -> (TcpStream, String) {
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "GET /ws HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}\r\n",
+            address, extensions
+        );
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(socket.read_u8().await.unwrap());
+        }
+        (socket, String::from_utf8(head).unwrap())
+    }
+
+    /// Sends a masked text frame, setting RSV1 if `payload` is compressed.
+    async fn send_frame(socket: &mut TcpStream, payload: &[u8], compressed: bool) {
+        let first = if compressed { 0xC1 } else { 0x81 };
+        let mut frame = vec![first, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+        // An all-zero mask leaves the payload as it is.
+        frame.extend_from_slice(payload);
+        socket.write_all(&frame).await.unwrap();
+    }
+
+    /// Reads one unmasked frame, returning its first byte and its payload.
+    async fn read_frame(socket: &mut TcpStream) -> (u8, Vec<u8>) {
+        let first = socket.read_u8().await.unwrap();
+        let len = match socket.read_u8().await.unwrap() {
+            126 => socket.read_u16().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        socket.read_exact(&mut payload).await.unwrap();
+        (first, payload)
+    }
+
+    fn deflate(text: &str) -> Vec<u8> {
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut output = Vec::with_capacity(text.len() + 64);
+        compress
+            .compress_vec(text.as_bytes(), &mut output, FlushCompress::Sync)
+            .unwrap();
+        // Messages leave out the empty block that ends a sync flush (RFC 7692, 7.2.1).
+        output.truncate(output.len() - 4);
+        output
+    }
+
+    fn inflate(payload: &[u8]) -> String {
+        let mut input = payload.to_vec();
+        input.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        let mut output = Vec::with_capacity(4096);
+        Decompress::new(false)
+            .decompress_vec(&input, &mut output, FlushDecompress::Sync)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn negotiates_permessage_deflate() {
+        let address = echo_server(true).await;
+        let (mut socket, head) =
+            handshake(address, "Sec-WebSocket-Extensions: permessage-deflate\r\n").await;
+        assert!(head.starts_with("HTTP/1.1 101"));
+        assert!(head
+            .to_ascii_lowercase()
+            .contains("sec-websocket-extensions: permessage-deflate"));
+
+        let text = r#"{"type":"event","button":"A","state":"PRESSED"}"#.repeat(3);
+        send_frame(&mut socket, &deflate(&text), true).await;
+        let (first, payload) = read_frame(&mut socket).await;
+        assert_eq!(first, 0xC1, "expected a compressed text frame");
+        assert!(payload.len() < text.len());
+        assert_eq!(inflate(&payload), text);
+    }
+
+    #[tokio::test]
+    async fn sends_plain_frames_unless_compression_is_negotiated() {
+        for (compression, offer) in [
+            (true, ""),
+            (false, "Sec-WebSocket-Extensions: permessage-deflate\r\n"),
+        ] {
+            let address = echo_server(compression).await;
+            let (mut socket, head) = handshake(address, offer).await;
+            assert!(!head
+                .to_ascii_lowercase()
+                .contains("sec-websocket-extensions"));
+
+            send_frame(&mut socket, b"hello", false).await;
+            assert_eq!(read_frame(&mut socket).await, (0x81, b"hello".to_vec()));
+        }
+    }
+}